@@ -0,0 +1,103 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Errors that say which path failed, and at what step.
+
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Failure of a single step of a run, with the paths involved.
+///
+/// Carried inside of the [`io::Error`]s returned and logged during a run,
+/// which keep the [`io::ErrorKind`] of the underlying error. See
+/// [`Self::from_io`] to get it back out.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum DelinkError {
+    /// Reading where the link at `path` points failed.
+    ReadLink { path: PathBuf, source: io::Error },
+    /// Listing the entries of the directory at `path` failed.
+    ReadDir { path: PathBuf, source: io::Error },
+    /// Creating the directory at `path` to populate failed.
+    CreateDir { path: PathBuf, source: io::Error },
+    /// Reading input paths from the file at `path` failed.
+    ReadPaths { path: PathBuf, source: io::Error },
+    /// Copying the file `src` to replace the link at `dest` failed.
+    Copy {
+        src: PathBuf,
+        dest: PathBuf,
+        source: io::Error,
+    },
+    /// Resolving the link or directory at `path` failed at any other step.
+    Resolve { path: PathBuf, source: io::Error },
+}
+
+impl DelinkError {
+    /// The [`DelinkError`] inside of `error`, if any.
+    pub fn from_io(error: &io::Error) -> Option<&Self> {
+        error.get_ref()?.downcast_ref()
+    }
+
+    /// The path that the failed step was acting on.
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::ReadLink { path, .. }
+            | Self::ReadDir { path, .. }
+            | Self::CreateDir { path, .. }
+            | Self::ReadPaths { path, .. }
+            | Self::Copy { dest: path, .. }
+            | Self::Resolve { path, .. } => path,
+        }
+    }
+
+    fn io_error(&self) -> &io::Error {
+        match self {
+            Self::ReadLink { source, .. }
+            | Self::ReadDir { source, .. }
+            | Self::CreateDir { source, .. }
+            | Self::ReadPaths { source, .. }
+            | Self::Copy { source, .. }
+            | Self::Resolve { source, .. } => source,
+        }
+    }
+}
+
+impl Display for DelinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReadLink { path, source } => write!(f, "Cannot read link {path:?}: {source}"),
+            Self::ReadDir { path, source } => {
+                write!(f, "Cannot read directory {path:?}: {source}")
+            }
+            Self::CreateDir { path, source } => {
+                write!(f, "Cannot create directory {path:?}: {source}")
+            }
+            Self::ReadPaths { path, source } => {
+                write!(f, "Cannot read paths from {path:?}: {source}")
+            }
+            Self::Copy { src, dest, source } => {
+                write!(f, "Cannot copy {src:?} to {dest:?}: {source}")
+            }
+            Self::Resolve { path, source } => write!(f, "Cannot resolve {path:?}: {source}"),
+        }
+    }
+}
+
+impl Error for DelinkError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.io_error())
+    }
+}
+
+impl From<DelinkError> for io::Error {
+    fn from(e: DelinkError) -> Self {
+        Self::new(e.io_error().kind(), e)
+    }
+}
@@ -0,0 +1,210 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Newline delimited record of replaced links, used to undo a run.
+//!
+//! Each record is `<ACTION>\t<LINK>\t<TARGET>`, where `TARGET` is the
+//! original contents of the link. Backslashes, tabs, and newlines in paths
+//! are escaped with a backslash.
+
+use std::{
+    ffi::{OsStr, OsString},
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+/// How a link was replaced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Replacement {
+    Copy,
+    Hardlink,
+    Populate,
+    Move,
+    Prune,
+    Symlink,
+}
+
+impl Replacement {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Copy => "COPY",
+            Self::Hardlink => "HARDLINK",
+            Self::Populate => "POPULATE",
+            Self::Move => "MOVE",
+            Self::Prune => "PRUNE",
+            Self::Symlink => "SYMLINK",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "COPY" => Some(Self::Copy),
+            "HARDLINK" => Some(Self::Hardlink),
+            "POPULATE" => Some(Self::Populate),
+            "MOVE" => Some(Self::Move),
+            "PRUNE" => Some(Self::Prune),
+            "SYMLINK" => Some(Self::Symlink),
+            _ => None,
+        }
+    }
+}
+
+/// A single replaced link.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Entry {
+    pub(crate) replacement: Replacement,
+    pub(crate) link: PathBuf,
+    pub(crate) target: PathBuf,
+}
+
+/// Append-only journal file.
+pub(crate) struct Journal {
+    file: File,
+}
+
+impl Journal {
+    /// Opens `path` for appending, so journals from multiple runs stack.
+    pub(crate) fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            file: OpenOptions::new().create(true).append(true).open(path)?,
+        })
+    }
+
+    /// Another handle to the same journal, for recording from another thread.
+    pub(crate) fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            file: self.file.try_clone()?,
+        })
+    }
+
+    /// Records that `link`, originally pointing to `target`, was replaced.
+    pub(crate) fn record(
+        &mut self,
+        replacement: Replacement,
+        link: &Path,
+        target: &Path,
+    ) -> io::Result<()> {
+        let mut line = replacement.as_str().as_bytes().to_vec();
+        line.push(b'\t');
+        escape(link.as_os_str(), &mut line);
+        line.push(b'\t');
+        escape(target.as_os_str(), &mut line);
+        line.push(b'\n');
+        // Written in one call, so an interrupted run keeps whole records
+        self.file.write_all(&line)
+    }
+}
+
+/// Reads all entries from the journal at `path`, in recorded order.
+pub(crate) fn read(path: &Path) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for line in BufReader::new(File::open(path)?).split(b'\n') {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut fields = line.split(|&b| b == b'\t');
+        let (Some(replacement), Some(link), Some(target), None) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid_record(&line));
+        };
+        let replacement = str::from_utf8(replacement)
+            .ok()
+            .and_then(Replacement::parse)
+            .ok_or_else(|| invalid_record(&line))?;
+
+        entries.push(Entry {
+            replacement,
+            link: unescape(link)?.into(),
+            target: unescape(target)?.into(),
+        });
+    }
+    Ok(entries)
+}
+
+fn invalid_record(line: &[u8]) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("Invalid journal record: {}", String::from_utf8_lossy(line)),
+    )
+}
+
+pub(crate) fn escape(s: &OsStr, out: &mut Vec<u8>) {
+    for &b in s.as_encoded_bytes() {
+        match b {
+            b'\\' => out.extend_from_slice(b"\\\\"),
+            b'\t' => out.extend_from_slice(b"\\t"),
+            b'\n' => out.extend_from_slice(b"\\n"),
+            b => out.push(b),
+        }
+    }
+}
+
+fn unescape(field: &[u8]) -> io::Result<OsString> {
+    let mut bytes = Vec::with_capacity(field.len());
+    let mut field = field.iter();
+    while let Some(&b) = field.next() {
+        if b == b'\\' {
+            match field.next() {
+                Some(b'\\') => bytes.push(b'\\'),
+                Some(b't') => bytes.push(b'\t'),
+                Some(b'n') => bytes.push(b'\n'),
+                _ => return Err(invalid_record(&bytes)),
+            }
+        } else {
+            bytes.push(b);
+        }
+    }
+    bytes_to_os_string(bytes)
+}
+
+#[cfg(unix)]
+fn bytes_to_os_string(bytes: Vec<u8>) -> io::Result<OsString> {
+    use std::os::unix::ffi::OsStringExt;
+    Ok(OsString::from_vec(bytes))
+}
+
+#[cfg(windows)]
+fn bytes_to_os_string(bytes: Vec<u8>) -> io::Result<OsString> {
+    String::from_utf8(bytes)
+        .map(OsString::from)
+        .map_err(|e| invalid_record(e.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp::Temp;
+
+    #[test]
+    fn round_trip_escapes() {
+        let file = Temp::new_file().unwrap();
+
+        let entries = [
+            Entry {
+                replacement: Replacement::Copy,
+                link: PathBuf::from("/tab\there"),
+                target: PathBuf::from("new\nline"),
+            },
+            Entry {
+                replacement: Replacement::Populate,
+                link: PathBuf::from("back\\slash"),
+                target: PathBuf::from("../plain"),
+            },
+        ];
+
+        let mut journal = Journal::open(&file).unwrap();
+        for entry in &entries {
+            journal
+                .record(entry.replacement, &entry.link, &entry.target)
+                .unwrap();
+        }
+        assert_eq!(read(&file).unwrap(), entries);
+    }
+}
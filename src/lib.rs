@@ -0,0 +1,8309 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Recursively resolves soft links into the contents they point to.
+
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+#[cfg(windows)]
+use std::os::windows::fs::FileTypeExt;
+mod error;
+mod journal;
+mod manifest;
+mod progress;
+#[cfg(test)]
+mod temp;
+
+pub use error::DelinkError;
+pub use glob::Pattern;
+use journal::{Journal, Replacement};
+use progress::{Progress, StatusLine};
+use similar::TextDiff;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet, VecDeque},
+    env,
+    ffi::{OsStr, OsString},
+    fmt::{self, Display, Write as _},
+    fs::{self, File, FileTimes, remove_file},
+    io::{self, BufRead, BufReader, ErrorKind, IsTerminal, Read, Write},
+    mem,
+    num::NonZeroUsize,
+    path::{self, Component, Path, PathBuf},
+    process,
+    sync::{Mutex, mpsc},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Configuration for a resolution run.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Log all actions without modifying the filesystem.
+    pub dry_run: bool,
+    /// Hard link files instead of copying them, when on the same filesystem.
+    pub hardlink: bool,
+    /// Do not log a [`Summary`] at the end of the run.
+    pub no_summary: bool,
+    /// Do not log anything, including the [`Summary`].
+    pub quiet: bool,
+    /// Only log the [`Summary`], even if [`Self::no_summary`] or
+    /// [`Self::quiet`] is set.
+    pub count_only: bool,
+    /// Log how long the run took once it is done, even if [`Self::quiet`]
+    /// is set, such as `ELAPSED 1.5s`.
+    pub time: bool,
+    /// How each logged line is formatted.
+    pub format: Format,
+    /// How each failure, or warning, is formatted on stderr, with
+    /// [`write_error`].
+    pub error_format: Format,
+    /// Color the leading words of [`Format::Text`] lines for terminals, such
+    /// as `COPY` in green and `SKIP` in yellow.
+    ///
+    /// Errors are only colored when stderr is a terminal.
+    pub color: bool,
+    /// Template for each [`Format::Text`] line, with `{action}`, `{dest}`,
+    /// and `{link}` placeholders.
+    ///
+    /// Paths are filled in quoted, and `{dest}` is left empty for actions
+    /// with only one path. Does not apply to the [`Summary`].
+    pub log_template: Option<String>,
+    /// Also log what each replaced link originally pointed to, as stored in
+    /// the link, e.g. `COPY <DEST> (was -> <TARGET>) => <LINK>`.
+    ///
+    /// Does not apply to [`Self::log_template`].
+    pub show_target: bool,
+    /// Log every link passed through on the way to each destination, as a
+    /// [`Format::Text`] line like `CHAIN <LINK> -> <LINK> -> <DEST>`.
+    ///
+    /// Each hop is joined to the directory of the link before it, without
+    /// resolving anything else.
+    pub show_chain: bool,
+    /// Print a unified diff under each [`Format::Text`] `COPY` line that
+    /// replaces an existing file, such as one merged into with
+    /// [`Self::merge`], from its contents now to what it is replaced with.
+    ///
+    /// Binary files only get a `(binary)` note. Mostly useful with
+    /// [`Self::dry_run`].
+    pub diff: bool,
+    /// Append how long each copy took, and how fast it went, to each
+    /// [`Format::Text`] `COPY` line, e.g. `(1.2 MB in 5ms, 240 MB/s)`.
+    ///
+    /// Lines are then logged once each copy finishes, instead of before it
+    /// starts. Dry runs copy nothing, so they have no timings. Does not apply
+    /// to [`Self::log_template`].
+    pub timing: bool,
+    /// Check the inputs again once done, logging any link left in place
+    /// that was not deliberately kept, e.g. by a skip or [`Self::shallow`].
+    ///
+    /// Each is logged as `VERIFY LEFTOVER <LINK>` and counted in
+    /// [`Summary::errors`], otherwise `VERIFY OK` is logged. Ignored for dry
+    /// runs.
+    pub verify: bool,
+    /// Log paths relative to this directory, instead of as absolute paths.
+    ///
+    /// New link contents, such as from [`Self::relativize`], are logged as
+    /// is.
+    pub relative_to: Option<PathBuf>,
+    /// Number of directory levels below each input to descend into.
+    ///
+    /// Unlimited when unset.
+    pub max_depth: Option<usize>,
+    /// Only resolve links directly inside of input directories, without
+    /// walking their real subdirectories.
+    ///
+    /// Links to directories are still populated.
+    pub no_recurse: bool,
+    /// Stop the run before copying more than this many bytes in total.
+    ///
+    /// Dry runs stop at the same point, so this can be checked beforehand.
+    /// Input paths are processed one at a time, ignoring [`Options::jobs`] and
+    /// [`Options::tree_jobs`].
+    pub max_total_bytes: Option<u64>,
+    /// Stop the run once this many links are replaced with what they point
+    /// to, logging the next link as [`Action::StopLimit`].
+    ///
+    /// Entries of populated directories are not counted, since each
+    /// directory replaces its link at once. Input paths are processed one at
+    /// a time, ignoring [`Options::jobs`] and [`Options::tree_jobs`].
+    pub limit: Option<usize>,
+    /// Rename replaced links by appending this suffix, instead of removing
+    /// them.
+    pub backup: Option<OsString>,
+    /// Append a record of each replaced link to this file, for [`undo`].
+    pub journal: Option<PathBuf>,
+    /// Write each replaced link and the canonical source it was resolved
+    /// from to this file, sorted by link, unless this is a dry run.
+    ///
+    /// Entries of populated directories are included.
+    pub manifest: Option<PathBuf>,
+    /// Keep the access and modification times of copied files.
+    pub preserve_timestamps: bool,
+    /// Whether copies share their contents with the original, keeping them
+    /// sparse and copy-on-write.
+    pub reflink: Reflink,
+    /// Keep the access and modification times of links that are recreated,
+    /// instead of resolved.
+    pub preserve_link_times: bool,
+    /// Rewrite absolute links to relative links with the same destination,
+    /// instead of resolving them.
+    pub relativize: bool,
+    /// Rewrite relative links to absolute links to their canonical
+    /// destination, instead of resolving them.
+    pub absolutize: bool,
+    /// Recreate FIFOs, sockets, and device files with `mknod`, instead of
+    /// skipping them.
+    ///
+    /// Only supported on Unix, and ignored elsewhere.
+    pub recreate_special: bool,
+    /// Give populated directories the permissions of the directory they were
+    /// populated from.
+    pub preserve_dir_perms: bool,
+    /// Populate the files of every directory below a populated directory
+    /// straight into it, leaving out the directories themselves.
+    ///
+    /// Names taken by an earlier entry are handled by the [`Collision`]
+    /// policy. Dry runs create nothing to collide with. Directories are
+    /// populated one at a time, ignoring [`Self::tree_jobs`].
+    pub flatten: Option<Collision>,
+    /// Keep the extended attributes of copied files.
+    ///
+    /// Only supported on Linux, and ignored elsewhere.
+    pub preserve_xattr: bool,
+    /// Keep the owning user and group of copied files and populated
+    /// directories.
+    ///
+    /// Usually only permitted for root, and a warning is printed for each
+    /// one that is not. Only supported on Unix, and ignored elsewhere.
+    pub preserve_owner: bool,
+    /// Only keep the execute bits of copied files, giving them the default
+    /// permissions of a new file otherwise.
+    ///
+    /// Wins over the mode put back with [`Self::preserve_owner`], so setuid
+    /// and setgid bits are cleared. Only supported on Unix, and ignored
+    /// elsewhere.
+    pub preserve_exec: bool,
+    /// Expand each input path as a glob pattern, in sorted order.
+    ///
+    /// Like shells, wildcards only match names starting with `.` when the
+    /// pattern does. Patterns that are invalid or match nothing are used as
+    /// is.
+    pub glob: bool,
+    /// Only resolve links with an absolute path matching one of these, when
+    /// not empty.
+    pub include: Vec<Pattern>,
+    /// Never resolve links with an absolute path matching one of these.
+    pub exclude: Vec<Pattern>,
+    /// Only replace links to files with one of these extensions, when not
+    /// empty, e.g. `so` and `dylib`.
+    ///
+    /// Checked against the resolved destination, not the link itself. Other
+    /// files are left as links.
+    pub extensions: Vec<OsString>,
+    /// Leave links to files larger than this many bytes in place.
+    ///
+    /// Checked against the resolved destination, like [`Self::extensions`].
+    pub skip_larger_than: Option<u64>,
+    /// Never resolve the links listed in this file, one path per line.
+    pub exclude_from: Option<PathBuf>,
+    /// Only resolve links that were themselves last modified at or after
+    /// this time, not counting their destinations.
+    ///
+    /// Links inside of populated directories are always resolved.
+    pub newer_than: Option<SystemTime>,
+    /// Only resolve links owned by the effective user of this process, such
+    /// as in directories shared with others.
+    ///
+    /// Links inside of populated directories are always resolved. Every link
+    /// counts as owned on platforms without user IDs.
+    pub mine_only: bool,
+    /// Only resolve links that point straight to a file or directory, leaving
+    /// links to other links in place.
+    ///
+    /// Only the target stored in each link is checked, regardless of how it
+    /// would be canonicalized. Links inside of populated directories are
+    /// always resolved.
+    pub require_concrete: bool,
+    /// Leave out entries of populated directories with names starting with
+    /// `.`, along with everything in them.
+    ///
+    /// Directory listings never include `.` and `..` themselves.
+    pub exclude_hidden: bool,
+    /// Recreate links inside of populated directories as links, with the
+    /// same target, instead of resolving them.
+    pub shallow: bool,
+    /// Only resolve links to directories, leaving links to files in place.
+    ///
+    /// Links to files inside of populated directories are linked to their
+    /// original location, while files themselves are still copied.
+    pub dirs_only: bool,
+    /// Walk each directory one level at a time, instead of finishing each
+    /// subdirectory before the next entry.
+    ///
+    /// Each populated directory is still finished before anything else, and
+    /// each entry populated with [`Self::tree_jobs`] is walked on its own.
+    pub breadth_first: bool,
+    /// Number of input paths to process at once.
+    ///
+    /// One at a time when unset, or with prompts on stdin, [`Self::limit`],
+    /// [`Self::max_total_bytes`] or [`Self::dedup`].
+    pub jobs: Option<NonZeroUsize>,
+    /// Number of entries of each populated directory link to process at once.
+    ///
+    /// Only the entries directly inside of links found outside of a
+    /// populated directory are split up. Lines are still logged in order.
+    /// One at a time when unset, or whenever [`Self::jobs`] would be.
+    pub tree_jobs: Option<NonZeroUsize>,
+    /// Also log every visited path, resolved link, and ignored file.
+    pub verbose: bool,
+    /// Remove links that point to nothing, instead of leaving them in place.
+    pub prune_broken: bool,
+    /// Leave every link that points to something in place, and only handle
+    /// links that point to nothing.
+    pub only_broken: bool,
+    /// Never populate a directory from a different filesystem.
+    pub same_filesystem: bool,
+    /// Resolve links to exactly what they point to, without following any
+    /// further links.
+    ///
+    /// A link to another link is replaced by a copy of that other link.
+    pub no_canonicalize: bool,
+    /// Resolve links by following their targets as written, without
+    /// resolving any other links along the way, like `pwd -L`.
+    ///
+    /// `..` removes the component before it, even if that is a link. Links
+    /// are otherwise resolved to their physical canonical destination.
+    pub logical: bool,
+    /// Never resolve links to anything outside of this directory.
+    pub root: Option<PathBuf>,
+    /// Follow absolute link targets inside of this directory, as if it were
+    /// `/`, such as for a mounted image of another system.
+    ///
+    /// Once inside, `..` never leaves it. Only applies to links found while
+    /// walking the inputs, not inside of populated directories.
+    pub target_root: Option<PathBuf>,
+    /// Copy each input path into this directory under its own name, with
+    /// every link resolved, instead of replacing links in place.
+    ///
+    /// The directory is created if missing, and the inputs are left
+    /// untouched. Copies are made like populated directories, and an input
+    /// with an existing copy fails, unless [`Self::merge`] is set.
+    pub into: Option<PathBuf>,
+    /// Merge each copy made with [`Self::into`] into an existing copy, such as
+    /// one left by an earlier run.
+    ///
+    /// Directories in both are merged, and anything else already there is
+    /// replaced, or renamed first with [`Self::backup`]. A directory is never
+    /// replaced by anything else, and a file already matching its copy is
+    /// left alone.
+    pub merge: bool,
+    /// Leave files already in an existing copy in place when merging with
+    /// [`Self::merge`], instead of replacing them.
+    pub no_clobber: bool,
+    /// Show a single updating status line on stderr, instead of logging each
+    /// action.
+    pub progress: bool,
+    /// Hard link every later link to a file to the first copy of that file,
+    /// instead of copying it again.
+    ///
+    /// Input paths are processed one at a time, ignoring [`Options::jobs`] and
+    /// [`Options::tree_jobs`].
+    pub dedup: bool,
+    /// Move files into the place of links to them, instead of copying them,
+    /// when on the same filesystem.
+    ///
+    /// Files with other links to them, or to a directory they are in, found
+    /// among the inputs are copied instead. Files inside of populated
+    /// directories are always copied.
+    pub move_files: bool,
+    /// Ask on stdin before replacing each link.
+    ///
+    /// Input paths are processed one at a time, ignoring [`Options::jobs`].
+    pub interactive: bool,
+    /// What to do once something fails.
+    pub on_error: OnError,
+    /// Try failed copies, renames, and removals up to this many more times,
+    /// when the error is likely to pass.
+    ///
+    /// Each try waits twice as long as the last, starting at 10 milliseconds.
+    pub retry: usize,
+}
+
+impl Options {
+    /// Checks if anything is read from stdin while running.
+    fn prompts(&self) -> bool {
+        self.interactive || self.on_error == OnError::Prompt
+    }
+
+    /// Checks if paths have to be handled one at a time, for prompts and
+    /// anything counted across the whole run.
+    fn serial(&self) -> bool {
+        self.prompts() || self.limit.is_some() || self.max_total_bytes.is_some() || self.dedup
+    }
+
+    /// Checks `path` against [`Self::include`] and [`Self::exclude`].
+    fn filter_matches(&self, path: &Path) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches_path(path)))
+            && !self.exclude.iter().any(|p| p.matches_path(path))
+    }
+
+    /// Checks the extension of `path` against [`Self::extensions`].
+    fn extension_allowed(&self, path: &Path) -> bool {
+        self.extensions.is_empty()
+            || path
+                .extension()
+                .is_some_and(|ext| self.extensions.iter().any(|allowed| allowed == ext))
+    }
+}
+
+/// Output format for logged lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Human readable lines, e.g. `COPY <DEST> => <LINK>`.
+    #[default]
+    Text,
+    /// One JSON object per line, e.g. `{"action":"copy","dest":...}`.
+    Json,
+}
+
+/// When to clone files instead of copying their contents.
+///
+/// Clones share their contents with the original on filesystems that
+/// support it, such as Btrfs and XFS, so sparse files stay sparse. Only
+/// supported on Linux.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Reflink {
+    /// Clone when supported, and copy otherwise.
+    #[default]
+    Auto,
+    /// Always clone, failing when that is not supported.
+    Always,
+    /// Always copy.
+    Never,
+}
+
+/// What to do with entries of a directory flattened with [`Options::flatten`]
+/// that have the same name as an earlier entry.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Collision {
+    /// Add a counter to the name, like `name-1.ext`.
+    #[default]
+    Rename,
+    /// Leave out everything after the first entry.
+    Skip,
+}
+
+/// What to do once something fails.
+///
+/// Every failure is logged to stderr and counted in [`Summary::errors`]
+/// first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnError {
+    /// Skip what failed and continue.
+    ///
+    /// Entries that cannot be accessed are skipped on their own, and any
+    /// other failure skips the rest of its input path.
+    #[default]
+    Skip,
+    /// Stop the whole run, returning the error.
+    Abort,
+    /// Ask on stdin whether to skip or stop.
+    ///
+    /// Input paths are processed one at a time, ignoring [`Options::jobs`].
+    Prompt,
+}
+
+/// Tally of the actions taken during a run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// Files copied into place.
+    pub copies: usize,
+    /// Files hard linked into place.
+    pub hardlinks: usize,
+    /// Files moved into place.
+    pub moves: usize,
+    /// Directories populated into place.
+    pub populated: usize,
+    /// Links left untouched.
+    pub skipped: usize,
+    /// Input paths that failed partway through.
+    pub errors: usize,
+    /// Total size of the files copied.
+    pub bytes: u64,
+    /// Canonical files and directories that replaced at least one link.
+    ///
+    /// Fewer than the links to files means [`Options::dedup`] can save
+    /// space.
+    pub distinct_targets: usize,
+    /// Time taken by the run.
+    pub elapsed: Duration,
+}
+
+impl Summary {
+    fn record(&mut self, action: &Action<'_>) {
+        match action {
+            Action::Copy { .. } => self.copies += 1,
+            Action::Hardlink { .. } => self.hardlinks += 1,
+            Action::Move { .. } => self.moves += 1,
+            Action::Populate { .. } => self.populated += 1,
+            Action::Backup { .. }
+            | Action::Restore { .. }
+            | Action::Symlink { .. }
+            | Action::Relativize { .. }
+            | Action::Absolutize { .. }
+            | Action::Mknod { .. }
+            | Action::Visit(_)
+            | Action::Resolved { .. }
+            | Action::SkipFile(_)
+            | Action::PruneBroken(_)
+            | Action::AbortByteLimit(_)
+            | Action::StopLimit(_)
+            | Action::VerifyLeftover(_) => (),
+            Action::SkipSelfReference(_)
+            | Action::SkipCycle(_)
+            | Action::SkipRecursive(_)
+            | Action::SkipDepth(_)
+            | Action::SkipFilter(_)
+            | Action::SkipInput(_)
+            | Action::SkipDangling(_)
+            | Action::SkipPathTooLong(_)
+            | Action::SkipIndirect(_)
+            | Action::SkipCollision(_)
+            | Action::SkipVanished(_)
+            | Action::SkipNotOwner(_)
+            | Action::SkipExists(_)
+            | Action::SkipLarge(_)
+            | Action::SkipFileLink(_)
+            | Action::SkipNotLink(_)
+            | Action::SkipOld(_)
+            | Action::SkipHidden(_)
+            | Action::SkipCaseCollision(_)
+            | Action::SkipBroken(_)
+            | Action::SkipUnchanged(_)
+            | Action::SkipOutsideRoot(_)
+            | Action::SkipDeclined(_)
+            | Action::SkipXdev(_)
+            | Action::SkipContained(_)
+            | Action::SkipSpecial(_)
+            | Action::SkipExt(_)
+            | Action::SkipValid(_) => self.skipped += 1,
+        }
+    }
+}
+
+impl Summary {
+    /// Adds the tallies of `other` to this summary, as if both runs were one.
+    ///
+    /// Targets in both runs are counted twice in [`Self::distinct_targets`].
+    pub fn merge(&mut self, other: Self) {
+        self.copies += other.copies;
+        self.hardlinks += other.hardlinks;
+        self.moves += other.moves;
+        self.populated += other.populated;
+        self.skipped += other.skipped;
+        self.errors += other.errors;
+        self.bytes += other.bytes;
+        self.distinct_targets += other.distinct_targets;
+        self.elapsed += other.elapsed;
+    }
+
+    /// Formats every tally, including the elapsed seconds, as a single JSON
+    /// object.
+    pub fn stats_json(&self) -> String {
+        format!(
+            r#"{{"copies":{},"hardlinks":{},"moves":{},"populated":{},"skipped":{},"errors":{},"bytes":{},"distinct_targets":{},"elapsed_secs":{}}}"#,
+            self.copies,
+            self.hardlinks,
+            self.moves,
+            self.populated,
+            self.skipped,
+            self.errors,
+            self.bytes,
+            self.distinct_targets,
+            self.elapsed.as_secs_f64()
+        )
+    }
+
+    /// Formats the summary as a single JSON object.
+    fn json(&self) -> String {
+        format!(
+            r#"{{"action":"summary","copies":{},"hardlinks":{},"moves":{},"populated":{},"skipped":{},"errors":{},"bytes":{},"distinct_targets":{}}}"#,
+            self.copies,
+            self.hardlinks,
+            self.moves,
+            self.populated,
+            self.skipped,
+            self.errors,
+            self.bytes,
+            self.distinct_targets
+        )
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "SUMMARY copies={} hardlinks={} moves={} populated={} skipped={} errors={} bytes={} distinct_targets={}",
+            self.copies,
+            self.hardlinks,
+            self.moves,
+            self.populated,
+            self.skipped,
+            self.errors,
+            self.bytes,
+            self.distinct_targets
+        )
+    }
+}
+
+/// A single logged decision.
+///
+/// Displayed as the [`Format::Text`] line for it, e.g.
+/// `COPY <DEST> => <LINK>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action<'a> {
+    /// `link` was filled with the contents of the file `dest`.
+    Copy { dest: &'a Path, link: &'a Path },
+    /// `link` was replaced with a hard link to `dest`.
+    Hardlink { dest: &'a Path, link: &'a Path },
+    /// `dest` was moved to replace `link`.
+    Move { dest: &'a Path, link: &'a Path },
+    /// `link` was filled with the directory `dest`.
+    Populate { dest: &'a Path, link: &'a Path },
+    /// The original `link` was renamed to `backup`.
+    Backup { link: &'a Path, backup: &'a Path },
+    /// `link` was recreated from a journal.
+    Restore { target: &'a Path, link: &'a Path },
+    /// `link` was recreated as a link to `target`.
+    Symlink { target: &'a Path, link: &'a Path },
+    /// `link` was rewritten to the relative `target`.
+    Relativize { link: &'a Path, target: &'a Path },
+    /// `link` was rewritten to the absolute `target`.
+    Absolutize { link: &'a Path, target: &'a Path },
+    /// `link` was recreated as the same kind of node as `dest`.
+    Mknod { dest: &'a Path, link: &'a Path },
+    /// A path is being checked, with [`Options::verbose`].
+    Visit(&'a Path),
+    /// `link` points to `dest`, with [`Options::verbose`].
+    Resolved { link: &'a Path, dest: &'a Path },
+    /// A path is not a link or directory, with [`Options::verbose`].
+    SkipFile(&'a Path),
+    /// A link points to nothing.
+    SkipBroken(&'a Path),
+    /// A path already has the contents of its destination.
+    SkipUnchanged(&'a Path),
+    /// A destination is outside of [`Options::root`].
+    SkipOutsideRoot(&'a Path),
+    /// Replacing a link was declined with [`Options::interactive`].
+    SkipDeclined(&'a Path),
+    /// A destination is on another filesystem, with
+    /// [`Options::same_filesystem`].
+    SkipXdev(&'a Path),
+    /// An input is inside of, or repeats, another input.
+    SkipContained(&'a Path),
+    /// A link points to a FIFO, socket, or device.
+    SkipSpecial(&'a Path),
+    /// A link points to a file without one of [`Options::extensions`].
+    SkipExt(&'a Path),
+    /// A link points to something, with [`Options::only_broken`].
+    SkipValid(&'a Path),
+    /// A link pointed to nothing and was removed, with
+    /// [`Options::prune_broken`].
+    PruneBroken(&'a Path),
+    /// A link points to itself.
+    SkipSelfReference(&'a Path),
+    /// A link loops through other links.
+    SkipCycle(&'a Path),
+    /// A link points to a directory containing it.
+    SkipRecursive(&'a Path),
+    /// A directory is past [`Options::max_depth`].
+    SkipDepth(&'a Path),
+    /// A link was filtered out by [`Options::include`], [`Options::exclude`],
+    /// or [`Options::exclude_from`].
+    SkipFilter(&'a Path),
+    /// An input does not exist or is invalid.
+    SkipInput(&'a Path),
+    /// An input link leads to something that does not exist.
+    SkipDangling(&'a Path),
+    /// A path, or a name in it, is longer than the platform allows.
+    SkipPathTooLong(&'a Path),
+    /// A link points straight to another link, with
+    /// [`Options::require_concrete`].
+    SkipIndirect(&'a Path),
+    /// A flattened entry has the name of an earlier one, with
+    /// [`Collision::Skip`].
+    SkipCollision(&'a Path),
+    /// A destination was removed after it was resolved, before it was used.
+    SkipVanished(&'a Path),
+    /// A link is owned by another user, with [`Options::mine_only`].
+    SkipNotOwner(&'a Path),
+    /// A copy would replace an existing file, with [`Options::no_clobber`].
+    SkipExists(&'a Path),
+    /// A link points to a file larger than [`Options::skip_larger_than`].
+    SkipLarge(&'a Path),
+    /// A link points to a file, with [`Options::dirs_only`].
+    SkipFileLink(&'a Path),
+    /// An input path is neither a link nor a directory.
+    SkipNotLink(&'a Path),
+    /// A link was last modified before [`Options::newer_than`].
+    SkipOld(&'a Path),
+    /// An entry of a populated directory is hidden, with
+    /// [`Options::exclude_hidden`].
+    SkipHidden(&'a Path),
+    /// An entry of a populated directory would replace an earlier entry,
+    /// such as `File` and `file` on a case-insensitive filesystem.
+    SkipCaseCollision(&'a Path),
+    /// Copying to a link would pass [`Options::max_total_bytes`].
+    AbortByteLimit(&'a Path),
+    /// A link is left in place, and the run stops, after
+    /// [`Options::limit`] links were replaced.
+    StopLimit(&'a Path),
+    /// A link is left in place after the run, with [`Options::verify`].
+    VerifyLeftover(&'a Path),
+}
+
+impl Display for Action<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verb = self.verb();
+        match (self, self.parts()) {
+            (Self::Backup { link, backup }, _) => write!(f, "{verb} {link:?} => {backup:?}"),
+            (Self::Relativize { link, target } | Self::Absolutize { link, target }, _) => {
+                write!(f, "{verb} {link:?} => {target:?}")
+            }
+            (Self::Resolved { link, dest }, _) => write!(f, "{verb} {link:?} -> {dest:?}"),
+            (_, (Some(dest), link)) => write!(f, "{verb} {dest:?} => {link:?}"),
+            (_, (None, path)) => write!(f, "{verb} {path:?}"),
+        }
+    }
+}
+
+impl Action<'_> {
+    /// Leading word(s) of the logged line.
+    pub fn verb(&self) -> &'static str {
+        match self {
+            Self::Copy { .. } => "COPY",
+            Self::Hardlink { .. } => "HARDLINK",
+            Self::Move { .. } => "MOVE",
+            Self::Populate { .. } => "POPULATE",
+            Self::Backup { .. } => "BACKUP",
+            Self::Restore { .. } => "RESTORE",
+            Self::Symlink { .. } => "SYMLINK",
+            Self::Relativize { .. } => "RELATIVIZE",
+            Self::Absolutize { .. } => "ABSOLUTIZE",
+            Self::Mknod { .. } => "MKNOD",
+            Self::Visit(_) => "VISIT",
+            Self::Resolved { .. } => "RESOLVED",
+            Self::SkipFile(_) => "SKIP FILE",
+            Self::SkipBroken(_) => "SKIP BROKEN",
+            Self::SkipUnchanged(_) => "SKIP UNCHANGED",
+            Self::SkipOutsideRoot(_) => "SKIP OUTSIDE ROOT",
+            Self::SkipDeclined(_) => "SKIP DECLINED",
+            Self::SkipXdev(_) => "SKIP XDEV",
+            Self::SkipContained(_) => "SKIP CONTAINED",
+            Self::SkipSpecial(_) => "SKIP SPECIAL",
+            Self::SkipExt(_) => "SKIP EXT",
+            Self::SkipValid(_) => "SKIP VALID",
+            Self::PruneBroken(_) => "PRUNE BROKEN",
+            Self::SkipSelfReference(_) => "SKIP SELF REFERENCE",
+            Self::SkipCycle(_) => "SKIP CYCLE",
+            Self::SkipRecursive(_) => "SKIP RECURSIVE",
+            Self::SkipDepth(_) => "SKIP DEPTH",
+            Self::SkipFilter(_) => "SKIP FILTER",
+            Self::SkipInput(_) => "SKIP INPUT",
+            Self::SkipDangling(_) => "SKIP DANGLING",
+            Self::SkipPathTooLong(_) => "SKIP PATH TOO LONG",
+            Self::SkipIndirect(_) => "SKIP INDIRECT",
+            Self::SkipCollision(_) => "SKIP COLLISION",
+            Self::SkipVanished(_) => "SKIP VANISHED",
+            Self::SkipNotOwner(_) => "SKIP NOT OWNER",
+            Self::SkipExists(_) => "SKIP EXISTS",
+            Self::SkipLarge(_) => "SKIP LARGE",
+            Self::SkipFileLink(_) => "SKIP FILE LINK",
+            Self::SkipNotLink(_) => "SKIP NOT A LINK",
+            Self::SkipOld(_) => "SKIP OLD",
+            Self::SkipHidden(_) => "SKIP HIDDEN",
+            Self::SkipCaseCollision(_) => "SKIP CASE COLLISION",
+            Self::AbortByteLimit(_) => "ABORT BYTE LIMIT",
+            Self::StopLimit(_) => "STOP LIMIT REACHED",
+            Self::VerifyLeftover(_) => "VERIFY LEFTOVER",
+        }
+    }
+
+    /// The destination, if any, and the path that was acted on.
+    pub fn parts(&self) -> (Option<&Path>, &Path) {
+        match self {
+            Self::Copy { dest, link }
+            | Self::Hardlink { dest, link }
+            | Self::Move { dest, link }
+            | Self::Mknod { dest, link }
+            | Self::Populate { dest, link }
+            | Self::Resolved { link, dest } => (Some(dest), link),
+            Self::Backup { link, backup } => (Some(backup), link),
+            Self::Restore { target, link }
+            | Self::Symlink { target, link }
+            | Self::Relativize { link, target }
+            | Self::Absolutize { link, target } => (Some(target), link),
+            Self::Visit(path)
+            | Self::SkipFile(path)
+            | Self::SkipBroken(path)
+            | Self::SkipUnchanged(path)
+            | Self::SkipOutsideRoot(path)
+            | Self::SkipDeclined(path)
+            | Self::SkipXdev(path)
+            | Self::SkipContained(path)
+            | Self::SkipSpecial(path)
+            | Self::SkipExt(path)
+            | Self::SkipValid(path)
+            | Self::PruneBroken(path)
+            | Self::SkipSelfReference(path)
+            | Self::SkipCycle(path)
+            | Self::SkipRecursive(path)
+            | Self::SkipDepth(path)
+            | Self::SkipFilter(path)
+            | Self::SkipInput(path)
+            | Self::SkipDangling(path)
+            | Self::SkipPathTooLong(path)
+            | Self::SkipIndirect(path)
+            | Self::SkipCollision(path)
+            | Self::SkipVanished(path)
+            | Self::SkipNotOwner(path)
+            | Self::SkipExists(path)
+            | Self::SkipLarge(path)
+            | Self::SkipFileLink(path)
+            | Self::SkipNotLink(path)
+            | Self::SkipOld(path)
+            | Self::SkipHidden(path)
+            | Self::SkipCaseCollision(path)
+            | Self::AbortByteLimit(path)
+            | Self::StopLimit(path)
+            | Self::VerifyLeftover(path) => (None, path),
+        }
+    }
+
+    /// The path that was acted on.
+    pub fn path(&self) -> &Path {
+        self.parts().1
+    }
+
+    /// Checks if the destination from [`Self::parts`] is somewhere on the
+    /// filesystem, rather than the contents of a link.
+    fn dest_is_location(&self) -> bool {
+        !matches!(
+            self,
+            Self::Restore { .. }
+                | Self::Symlink { .. }
+                | Self::Relativize { .. }
+                | Self::Absolutize { .. }
+        )
+    }
+
+    /// The path this action replaced a link with the contents of, if any.
+    fn source(&self) -> Option<&Path> {
+        match self {
+            Self::Copy { dest, .. }
+            | Self::Hardlink { dest, .. }
+            | Self::Move { dest, .. }
+            | Self::Populate { dest, .. }
+            | Self::Mknod { dest, .. } => Some(dest),
+            _ => None,
+        }
+    }
+
+    /// The link deliberately left in place by this action, if any.
+    fn kept_link(&self) -> Option<&Path> {
+        match self {
+            Self::Backup { backup, .. } => Some(backup),
+            Self::Copy { .. }
+            | Self::Hardlink { .. }
+            | Self::Move { .. }
+            | Self::Populate { .. }
+            | Self::Mknod { .. }
+            | Self::Restore { .. }
+            | Self::Visit(_)
+            | Self::Resolved { .. }
+            | Self::SkipFile(_)
+            | Self::SkipContained(_)
+            | Self::PruneBroken(_)
+            | Self::AbortByteLimit(_)
+            | Self::VerifyLeftover(_) => None,
+            _ => Some(self.path()),
+        }
+    }
+
+    /// The same action with different [`Self::parts`].
+    ///
+    /// `dest` is ignored for actions without one.
+    fn with_parts<'b>(&self, dest: &'b Path, link: &'b Path) -> Action<'b> {
+        match self {
+            Self::Copy { .. } => Action::Copy { dest, link },
+            Self::Hardlink { .. } => Action::Hardlink { dest, link },
+            Self::Move { .. } => Action::Move { dest, link },
+            Self::Populate { .. } => Action::Populate { dest, link },
+            Self::Backup { .. } => Action::Backup { backup: dest, link },
+            Self::Restore { .. } => Action::Restore { target: dest, link },
+            Self::Symlink { .. } => Action::Symlink { target: dest, link },
+            Self::Relativize { .. } => Action::Relativize { target: dest, link },
+            Self::Absolutize { .. } => Action::Absolutize { target: dest, link },
+            Self::Mknod { .. } => Action::Mknod { dest, link },
+            Self::Visit(_) => Action::Visit(link),
+            Self::Resolved { .. } => Action::Resolved { dest, link },
+            Self::SkipFile(_) => Action::SkipFile(link),
+            Self::SkipBroken(_) => Action::SkipBroken(link),
+            Self::SkipUnchanged(_) => Action::SkipUnchanged(link),
+            Self::SkipOutsideRoot(_) => Action::SkipOutsideRoot(link),
+            Self::SkipDeclined(_) => Action::SkipDeclined(link),
+            Self::SkipXdev(_) => Action::SkipXdev(link),
+            Self::SkipContained(_) => Action::SkipContained(link),
+            Self::SkipSpecial(_) => Action::SkipSpecial(link),
+            Self::SkipExt(_) => Action::SkipExt(link),
+            Self::SkipValid(_) => Action::SkipValid(link),
+            Self::PruneBroken(_) => Action::PruneBroken(link),
+            Self::SkipSelfReference(_) => Action::SkipSelfReference(link),
+            Self::SkipCycle(_) => Action::SkipCycle(link),
+            Self::SkipRecursive(_) => Action::SkipRecursive(link),
+            Self::SkipDepth(_) => Action::SkipDepth(link),
+            Self::SkipFilter(_) => Action::SkipFilter(link),
+            Self::SkipInput(_) => Action::SkipInput(link),
+            Self::SkipDangling(_) => Action::SkipDangling(link),
+            Self::SkipPathTooLong(_) => Action::SkipPathTooLong(link),
+            Self::SkipIndirect(_) => Action::SkipIndirect(link),
+            Self::SkipCollision(_) => Action::SkipCollision(link),
+            Self::SkipVanished(_) => Action::SkipVanished(link),
+            Self::SkipNotOwner(_) => Action::SkipNotOwner(link),
+            Self::SkipExists(_) => Action::SkipExists(link),
+            Self::SkipLarge(_) => Action::SkipLarge(link),
+            Self::SkipFileLink(_) => Action::SkipFileLink(link),
+            Self::SkipNotLink(_) => Action::SkipNotLink(link),
+            Self::SkipOld(_) => Action::SkipOld(link),
+            Self::SkipHidden(_) => Action::SkipHidden(link),
+            Self::SkipCaseCollision(_) => Action::SkipCaseCollision(link),
+            Self::AbortByteLimit(_) => Action::AbortByteLimit(link),
+            Self::StopLimit(_) => Action::StopLimit(link),
+            Self::VerifyLeftover(_) => Action::VerifyLeftover(link),
+        }
+    }
+
+    /// Fills the `{action}`, `{dest}`, and `{link}` placeholders of
+    /// `template`.
+    ///
+    /// Paths are quoted, and `{dest}` is empty for actions without one.
+    fn render(&self, template: &str) -> String {
+        let (dest, link) = self.parts();
+        let mut line = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            line.push_str(&rest[..start]);
+            rest = &rest[start..];
+            if let Some(after) = rest.strip_prefix("{action}") {
+                line.push_str(self.verb());
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("{dest}") {
+                if let Some(dest) = dest {
+                    let _ = write!(line, "{dest:?}");
+                }
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("{link}") {
+                let _ = write!(line, "{link:?}");
+                rest = after;
+            } else {
+                line.push('{');
+                rest = &rest[1..];
+            }
+        }
+        line.push_str(rest);
+        line
+    }
+
+    /// Formats the action as a single JSON object.
+    fn json(&self) -> String {
+        match self {
+            Self::Copy { dest, link } => json_move("copy", dest, link),
+            Self::Hardlink { dest, link } => json_move("hardlink", dest, link),
+            Self::Move { dest, link } => json_move("move", dest, link),
+            Self::Populate { dest, link } => json_move("populate", dest, link),
+            Self::Backup { link, backup } => json_move("backup", backup, link),
+            Self::Restore { target, link } => json_move("restore", target, link),
+            Self::Symlink { target, link } => json_move("symlink", target, link),
+            Self::Relativize { link, target } => json_move("relativize", target, link),
+            Self::Absolutize { link, target } => json_move("absolutize", target, link),
+            Self::Mknod { dest, link } => json_move("mknod", dest, link),
+            Self::Visit(path) => json_skip("visit", path),
+            Self::Resolved { link, dest } => json_move("resolved", dest, link),
+            Self::SkipFile(path) => json_skip("skip_file", path),
+            Self::SkipBroken(path) => json_skip("skip_broken", path),
+            Self::SkipUnchanged(path) => json_skip("skip_unchanged", path),
+            Self::SkipOutsideRoot(path) => json_skip("skip_outside_root", path),
+            Self::SkipDeclined(path) => json_skip("skip_declined", path),
+            Self::SkipXdev(path) => json_skip("skip_xdev", path),
+            Self::SkipContained(path) => json_skip("skip_contained", path),
+            Self::SkipSpecial(path) => json_skip("skip_special", path),
+            Self::SkipExt(path) => json_skip("skip_ext", path),
+            Self::SkipValid(path) => json_skip("skip_valid", path),
+            Self::PruneBroken(path) => json_skip("prune_broken", path),
+            Self::SkipSelfReference(path) => json_skip("skip_self", path),
+            Self::SkipCycle(path) => json_skip("skip_cycle", path),
+            Self::SkipRecursive(path) => json_skip("skip_recursive", path),
+            Self::SkipDepth(path) => json_skip("skip_depth", path),
+            Self::SkipFilter(path) => json_skip("skip_filter", path),
+            Self::SkipInput(path) => json_skip("skip_input", path),
+            Self::SkipDangling(path) => json_skip("skip_dangling", path),
+            Self::SkipPathTooLong(path) => json_skip("skip_path_too_long", path),
+            Self::SkipIndirect(path) => json_skip("skip_indirect", path),
+            Self::SkipCollision(path) => json_skip("skip_collision", path),
+            Self::SkipVanished(path) => json_skip("skip_vanished", path),
+            Self::SkipNotOwner(path) => json_skip("skip_not_owner", path),
+            Self::SkipExists(path) => json_skip("skip_exists", path),
+            Self::SkipLarge(path) => json_skip("skip_large", path),
+            Self::SkipFileLink(path) => json_skip("skip_file_link", path),
+            Self::SkipNotLink(path) => json_skip("skip_not_a_link", path),
+            Self::SkipOld(path) => json_skip("skip_old", path),
+            Self::SkipHidden(path) => json_skip("skip_hidden", path),
+            Self::SkipCaseCollision(path) => json_skip("skip_case_collision", path),
+            Self::AbortByteLimit(path) => json_skip("abort_byte_limit", path),
+            Self::StopLimit(path) => json_skip("stop_limit", path),
+            Self::VerifyLeftover(path) => json_skip("verify_leftover", path),
+        }
+    }
+}
+
+fn json_move(action: &str, dest: &Path, link: &Path) -> String {
+    format!(
+        r#"{{"action":"{action}","dest":{},"link":{}}}"#,
+        JsonStr(dest),
+        JsonStr(link)
+    )
+}
+
+fn json_skip(action: &str, path: &Path) -> String {
+    format!(r#"{{"action":"{action}","path":{}}}"#, JsonStr(path))
+}
+
+/// Writes the failure `e` at `path` to `writer` as a single line.
+///
+/// [`Format::Text`] lines are `ERROR <PATH>: <MESSAGE>`, or
+/// `ERROR PERMISSION <PATH>: <MESSAGE>` when access was denied.
+/// [`Format::Json`] lines are objects with `path` and `message` fields.
+/// A [`None`] path is stdin, and is `null` in JSON. Text lines start in red
+/// if `color` is set.
+pub fn write_error<W: Write>(
+    writer: &mut W,
+    format: Format,
+    path: Option<&Path>,
+    e: &io::Error,
+    color: bool,
+) -> io::Result<()> {
+    match (format, path) {
+        (Format::Text, Some(path)) if e.kind() == ErrorKind::PermissionDenied => {
+            let verb = Paint::new("ERROR PERMISSION", color);
+            writeln!(writer, "{verb} {path:?}: {e}")
+        }
+        (Format::Text, Some(path)) => {
+            writeln!(writer, "{} {path:?}: {e}", Paint::new("ERROR", color))
+        }
+        (Format::Text, None) => writeln!(writer, "{} stdin: {e}", Paint::new("ERROR", color)),
+        (Format::Json, path) => writeln!(
+            writer,
+            r#"{{"path":{},"message":{}}}"#,
+            path.map_or_else(|| "null".to_string(), |path| JsonStr(path).to_string()),
+            JsonStr(e.to_string())
+        ),
+    }
+}
+
+/// Writes the warning `message` about `path` to `writer` as a single line.
+///
+/// [`Format::Text`] lines are `WARNING <PATH>: <MESSAGE>`, starting in yellow
+/// if `color` is set. [`Format::Json`] lines are objects like those of
+/// [`write_error`], with a `level` field of `"warning"`.
+fn write_warning<W: Write>(
+    writer: &mut W,
+    format: Format,
+    path: &Path,
+    message: &str,
+    color: bool,
+) -> io::Result<()> {
+    match format {
+        Format::Text => {
+            writeln!(
+                writer,
+                "{} {path:?}: {message}",
+                Paint::new("WARNING", color)
+            )
+        }
+        Format::Json => writeln!(
+            writer,
+            r#"{{"level":"warning","path":{},"message":{}}}"#,
+            JsonStr(path),
+            JsonStr(message)
+        ),
+    }
+}
+
+/// Leading words of a [`Format::Text`] line, colored with [`Options::color`].
+struct Paint<'a> {
+    verb: &'a str,
+    color: Option<&'static str>,
+}
+
+impl<'a> Paint<'a> {
+    fn new(verb: &'a str, color: bool) -> Self {
+        let color = color
+            .then(|| match verb {
+                "COPY" => Some("32"),
+                "POPULATE" => Some("34"),
+                _ if verb.starts_with("SKIP") || verb == "WARNING" => Some("33"),
+                _ if verb.starts_with("ERROR") => Some("31"),
+                _ => None,
+            })
+            .flatten();
+        Self { verb, color }
+    }
+}
+
+impl Display for Paint<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.color {
+            Some(code) => write!(f, "\x1b[{code}m{}\x1b[0m", self.verb),
+            None => f.write_str(self.verb),
+        }
+    }
+}
+
+/// Time taken to copy a file, displayed like `(1.2 MB in 5ms, 240 MB/s)`.
+struct Timing {
+    bytes: u64,
+    elapsed: Duration,
+}
+
+impl Timing {
+    /// Writes `bytes` in decimal units, e.g. `1.2 MB`.
+    fn write_bytes(f: &mut fmt::Formatter<'_>, bytes: f64) -> fmt::Result {
+        const UNITS: [&str; 5] = ["kB", "MB", "GB", "TB", "PB"];
+
+        if bytes < 1000.0 {
+            return write!(f, "{bytes} B");
+        }
+        let mut scaled = bytes / 1000.0;
+        let mut unit = 0;
+        while scaled >= 1000.0 && unit + 1 < UNITS.len() {
+            scaled /= 1000.0;
+            unit += 1;
+        }
+        write!(f, "{scaled:.1} {}", UNITS[unit])
+    }
+}
+
+impl Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_char('(')?;
+        Self::write_bytes(f, self.bytes as f64)?;
+        if self.elapsed < Duration::from_millis(1) {
+            write!(f, " in {}µs, ", self.elapsed.as_micros())?;
+        } else if self.elapsed < Duration::from_secs(1) {
+            write!(f, " in {}ms, ", self.elapsed.as_millis())?;
+        } else {
+            write!(f, " in {:.1}s, ", self.elapsed.as_secs_f64())?;
+        }
+        // Too fast to measure
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            Self::write_bytes(f, (self.bytes as f64 / secs).round())?;
+        } else {
+            f.write_char('?')?;
+        }
+        f.write_str("/s)")
+    }
+}
+
+/// Displays a path or string as a quoted and escaped JSON string.
+///
+/// Paths that are not valid UTF-8 are lossily converted.
+struct JsonStr<T>(T);
+
+impl<T: AsRef<OsStr>> Display for JsonStr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_char('"')?;
+        for c in self.0.as_ref().to_string_lossy().chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+                c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+                c => f.write_char(c)?,
+            }
+        }
+        f.write_char('"')
+    }
+}
+
+/// State threaded through a single run.
+struct Context<'a, W> {
+    writer: &'a mut W,
+    options: &'a Options,
+    summary: Summary,
+    /// Temporary directory being populated, and the path it will replace.
+    staging: Option<(PathBuf, PathBuf)>,
+    /// Copies a file into place, replaceable to inject failures in tests.
+    copy: fn(&Path, &Path) -> io::Result<u64>,
+    /// Called with each destination right before it is checked for the last
+    /// time, replaceable to remove it in tests.
+    before_use: fn(&Path),
+    journal: Option<Journal>,
+    /// Canonical [`Options::root`].
+    root: Option<PathBuf>,
+    /// Canonical [`Options::target_root`].
+    target_root: Option<PathBuf>,
+    /// Canonical [`Options::into`], or absolute until it is created.
+    into: Option<PathBuf>,
+    /// Absolute [`Options::relative_to`].
+    relative_to: Option<PathBuf>,
+    /// Reports each action when [`Options::progress`] is set.
+    progress: Option<Box<dyn Progress>>,
+    /// Answers to prompts when [`Options::interactive`] is set, or
+    /// [`Options::on_error`] is [`OnError::Prompt`].
+    answers: Option<Box<dyn BufRead>>,
+    /// Receives each failure, formatted by [`Options::error_format`].
+    errors: Box<dyn Write>,
+    /// Whether [`Self::errors`] are colored, with [`Options::color`].
+    color_errors: bool,
+    /// First copy of each canonical file, when [`Options::dedup`] is set.
+    copied: HashMap<PathBuf, PathBuf>,
+    /// Locations of the links listed in [`Options::exclude_from`].
+    excluded: HashSet<PathBuf>,
+    /// Number of links to each canonical destination among the inputs, when
+    /// [`Options::move_files`] is set.
+    references: HashMap<PathBuf, usize>,
+    /// Set once a failure stops the run, so it is not handled again.
+    aborted: bool,
+    /// Canonical source of each directory being populated, by its path.
+    populating: HashMap<PathBuf, PathBuf>,
+    /// Canonical directories already flattened into the directory being
+    /// populated, with [`Options::flatten`].
+    flattened: HashSet<PathBuf>,
+    /// Absolute paths of links deliberately left in place, when
+    /// [`Options::verify`] is set.
+    kept: HashSet<PathBuf>,
+    /// Receives each action instead of it being logged.
+    on_action: Option<&'a mut dyn FnMut(Action<'_>)>,
+    /// Number of actions taken so far, for [`Options::progress`].
+    processed: usize,
+    /// Replaced links and their sources, when [`Options::manifest`] is set.
+    manifest: HashMap<PathBuf, PathBuf>,
+    /// Number of links replaced outside of populated directories, for
+    /// [`Options::limit`].
+    replaced: usize,
+    /// Set once [`Options::limit`] is reached, so nothing else is processed.
+    stopped: bool,
+    /// Measured copy to annotate the next logged line with, for
+    /// [`Options::timing`].
+    timing: Option<Timing>,
+    /// File mode creation mask for [`Options::preserve_exec`], read before
+    /// any threads start.
+    umask: u32,
+    /// Canonical destinations that replaced a link, for
+    /// [`Summary::distinct_targets`].
+    targets: HashSet<PathBuf>,
+}
+
+impl<'a, W: Write> Context<'a, W> {
+    fn new(writer: &'a mut W, options: &'a Options) -> Self {
+        Self {
+            writer,
+            options,
+            summary: Summary::default(),
+            staging: None,
+            copy: match options.reflink {
+                Reflink::Auto => {
+                    |src, dest| reflink_file(src, dest).or_else(|_| copy_file(src, dest))
+                }
+                Reflink::Always => reflink_file,
+                Reflink::Never => copy_file,
+            },
+            before_use: |_| (),
+            journal: None,
+            root: None,
+            target_root: None,
+            into: None,
+            relative_to: None,
+            progress: (options.progress && !options.quiet)
+                .then(|| Box::new(StatusLine::default()) as Box<dyn Progress>),
+            answers: options
+                .prompts()
+                .then(|| Box::new(BufReader::new(io::stdin())) as Box<dyn BufRead>),
+            errors: Box::new(io::stderr()),
+            color_errors: options.color && io::stderr().is_terminal(),
+            copied: HashMap::new(),
+            excluded: HashSet::new(),
+            references: HashMap::new(),
+            aborted: false,
+            populating: HashMap::new(),
+            flattened: HashSet::new(),
+            kept: HashSet::new(),
+            on_action: None,
+            processed: 0,
+            manifest: HashMap::new(),
+            replaced: 0,
+            stopped: false,
+            timing: None,
+            umask: 0,
+            targets: HashSet::new(),
+        }
+    }
+
+    /// Opens [`Options::journal`] for recording, unless this is a dry run,
+    /// canonicalizes [`Options::root`], [`Options::target_root`], and
+    /// [`Options::into`], makes [`Options::relative_to`]
+    /// absolute, and reads [`Options::exclude_from`].
+    fn prepare(mut self) -> io::Result<Self> {
+        if let Some(path) = &self.options.journal
+            && !self.options.dry_run
+        {
+            self.journal = Some(Journal::open(path)?);
+        }
+        if let Some(root) = &self.options.root {
+            self.root = Some(canonicalize(root)?);
+        }
+        if let Some(root) = &self.options.target_root {
+            self.target_root = Some(canonicalize(root)?);
+        }
+        if let Some(into) = &self.options.into {
+            self.into = Some(canonicalize(into).or_else(|_| path::absolute(into))?);
+        }
+        if let Some(base) = &self.options.relative_to {
+            self.relative_to = Some(path::absolute(base)?);
+        }
+        if let Some(path) = &self.options.exclude_from {
+            for entry in read_paths(BufReader::new(File::open(path)?), b'\n') {
+                // Entries in missing directories can never match
+                self.excluded.extend(link_location(&entry?));
+            }
+        }
+        Ok(self)
+    }
+
+    fn log(&mut self, action: Action<'_>) {
+        // Only ever for this action
+        let timing = self.timing.take();
+        self.summary.record(&action);
+        self.processed += 1;
+        if self.staging.is_none() && action.source().is_some() {
+            self.replaced += 1;
+        }
+        if let Some(link) = action.kept_link() {
+            self.keep(link);
+        }
+        if self.options.manifest.is_some()
+            && let Some(source) = action.source()
+        {
+            // Hard links to an earlier copy come from that copy's source
+            let source =
+                (self.manifest.get(source).cloned()).unwrap_or_else(|| source.to_path_buf());
+            self.manifest.insert(action.path().to_path_buf(), source);
+        }
+        if let Some(on_action) = &mut self.on_action {
+            on_action(action);
+        } else if self.options.progress {
+            if let Some(progress) = &mut self.progress {
+                progress.update(&self.summary, self.processed, action.path());
+            }
+        } else if !(self.options.quiet || self.options.count_only) {
+            let target = self.raw_target(&action);
+            let timing = timing.map_or_else(String::new, |timing| format!(" {timing}"));
+            let relocated = self.relative_to.as_ref().map(|base| {
+                let (dest, link) = action.parts();
+                let dest = match dest {
+                    Some(dest) if action.dest_is_location() => relative_display(base, dest),
+                    dest => dest.map(Path::to_path_buf).unwrap_or_default(),
+                };
+                (dest, relative_display(base, link))
+            });
+            let action = match &relocated {
+                Some((dest, link)) => action.with_parts(dest, link),
+                None => action,
+            };
+            let _ = match (self.options.format, target) {
+                (Format::Text, _) if let Some(template) = &self.options.log_template => {
+                    writeln!(self.writer, "{}", action.render(template))
+                }
+                (Format::Text, Some(target)) => {
+                    let (dest, link) = action.parts();
+                    let dest = dest.unwrap_or(Path::new(""));
+                    writeln!(
+                        self.writer,
+                        "{} {dest:?} (was -> {target:?}) => {link:?}{timing}",
+                        Paint::new(action.verb(), self.options.color)
+                    )
+                }
+                (Format::Text, None) if self.options.color => {
+                    // Every line starts with the verb
+                    let line = action.to_string();
+                    let rest = &line[action.verb().len()..];
+                    let verb = Paint::new(action.verb(), true);
+                    writeln!(self.writer, "{verb}{rest}{timing}")
+                }
+                (Format::Text, None) => writeln!(self.writer, "{action}{timing}"),
+                (Format::Json, Some(target)) => {
+                    let mut json = action.json();
+                    json.pop();
+                    writeln!(self.writer, r#"{json},"target":{}}}"#, JsonStr(&target))
+                }
+                (Format::Json, None) => writeln!(self.writer, "{}", action.json()),
+            };
+        }
+    }
+
+    /// What the link replaced by `action` stored, when
+    /// [`Options::show_target`] is set.
+    fn raw_target(&self, action: &Action<'_>) -> Option<PathBuf> {
+        if !self.options.show_target {
+            return None;
+        }
+        match action {
+            Action::Copy { dest, link }
+            | Action::Hardlink { dest, link }
+            | Action::Move { dest, link }
+            | Action::Populate { dest, link } => {
+                // Links in a populated directory are still in the source
+                if self.staging.is_none() {
+                    read_link(link).ok()
+                } else {
+                    read_link(dest).ok()
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Prints the changes from the file at `path` to the contents of `dest`
+    /// replacing it, when [`Options::diff`] applies.
+    fn log_diff(&mut self, path: &Path, dest: &Path) {
+        if !self.options.diff
+            || self.options.format != Format::Text
+            || self.options.progress
+            || self.options.quiet
+            || self.options.count_only
+        {
+            return;
+        }
+        // Only a file of its own is overwritten, not a link or what it shows
+        if !path.symlink_metadata().is_ok_and(|meta| meta.is_file())
+            || canonicalize(path).ok() == canonicalize(dest).ok()
+        {
+            return;
+        }
+        let (Ok(old), Ok(new)) = (fs::read(path), fs::read(dest)) else {
+            return;
+        };
+        if old == new {
+            return;
+        }
+
+        let text = |bytes: Vec<u8>| {
+            String::from_utf8(bytes)
+                .ok()
+                .filter(|text| !text.contains('\0'))
+        };
+        let (old_name, new_name) = match &self.relative_to {
+            Some(base) => (relative_display(base, path), relative_display(base, dest)),
+            None => (path.to_path_buf(), dest.to_path_buf()),
+        };
+        let diff = match (text(old), text(new)) {
+            (Some(old), Some(new)) => TextDiff::from_lines(&old, &new)
+                .unified_diff()
+                .header(&format!("{old_name:?}"), &format!("{new_name:?}"))
+                .to_string(),
+            _ => "(binary)\n".to_string(),
+        };
+        let _ = write!(self.writer, "{diff}");
+    }
+
+    /// Logs copying `dest` to the link at `path`, followed by its diff.
+    ///
+    /// `timing` is the size copied and how long it took, for
+    /// [`Options::timing`].
+    fn log_copy(&mut self, dest: &Path, path: &Path, timing: Option<(u64, Duration)>) {
+        self.timing = timing.map(|(bytes, elapsed)| Timing { bytes, elapsed });
+        self.log(Action::Copy { dest, link: path });
+        self.log_diff(path, dest);
+    }
+
+    /// Prints each link from `link` to its destination, when
+    /// [`Options::show_chain`] applies.
+    fn log_chain(&mut self, link: &Path) {
+        // Same as the usual limit on Linux
+        const MAX_HOPS: usize = 40;
+
+        if !self.options.show_chain
+            || self.options.format != Format::Text
+            || self.options.progress
+            || self.options.quiet
+            || self.options.count_only
+        {
+            return;
+        }
+
+        let mut chain = vec![link.to_path_buf()];
+        while chain.len() <= MAX_HOPS
+            && let Some(current) = chain.last()
+            && let Ok(target) = read_link(current)
+        {
+            let next = match current.parent() {
+                Some(parent) => parent.join(target),
+                None => target,
+            };
+            chain.push(next);
+        }
+
+        let mut line = String::from("CHAIN");
+        for (idx, hop) in chain.iter().enumerate() {
+            let hop = match &self.relative_to {
+                Some(base) => relative_display(base, hop),
+                None => hop.clone(),
+            };
+            let separator = if idx == 0 { " " } else { " -> " };
+            let _ = write!(line, "{separator}{hop:?}");
+        }
+        let _ = writeln!(self.writer, "{line}");
+    }
+
+    /// Notes that `link`, or a directory that was not walked, was
+    /// deliberately left in place, for [`Options::verify`].
+    fn keep(&mut self, link: &Path) {
+        if self.options.verify {
+            self.kept
+                .insert(path::absolute(link).unwrap_or_else(|_| link.to_path_buf()));
+        }
+    }
+
+    /// Logs every link in or under `paths` that was not deliberately kept,
+    /// or that none are left.
+    fn verify(&mut self, paths: &[PathBuf]) {
+        let paths: Vec<_> = paths
+            .iter()
+            .map(|path| path::absolute(path).unwrap_or_else(|_| path.clone()))
+            .collect();
+        let mut leftover = Vec::new();
+        walk_links(&paths, |link| {
+            // Nothing under a skipped directory was walked
+            if !link.ancestors().any(|path| self.kept.contains(path)) {
+                leftover.push(link.to_path_buf());
+            }
+        });
+        // Repeated inputs are walked again
+        leftover.sort();
+        leftover.dedup();
+
+        for link in &leftover {
+            self.log(Action::VerifyLeftover(link));
+            self.summary.errors += 1;
+        }
+        if leftover.is_empty()
+            && !(self.options.quiet || self.options.count_only || self.options.progress)
+        {
+            let _ = match self.options.format {
+                Format::Text => writeln!(self.writer, "VERIFY OK"),
+                Format::Json => writeln!(self.writer, r#"{{"action":"verify_ok"}}"#),
+            };
+        }
+    }
+
+    /// Ends the [`Options::progress`] status line, if it was shown.
+    fn finish_progress(&mut self) {
+        if let Some(progress) = &mut self.progress {
+            progress.finish();
+        }
+    }
+
+    /// Logs an `action` only when [`Options::verbose`] is set.
+    fn log_verbose(&mut self, action: Action<'_>) {
+        if self.options.verbose {
+            self.log(action);
+        }
+    }
+}
+
+/// Returns the canonical destination of `path` if it is a symbolic link.
+///
+/// Self-referential links are logged to `writer` and treated as not being
+/// links. Links to nothing are also treated as not being links.
+pub fn resolve_symlink<W: Write>(path: &PathBuf, writer: &mut W) -> io::Result<Option<PathBuf>> {
+    Context::new(writer, &Options::default()).resolve_symlink(path)
+}
+
+impl<W: Write> Context<'_, W> {
+    fn resolve_symlink(&mut self, path: &PathBuf) -> io::Result<Option<PathBuf>> {
+        fn link_target(path: &Path, target_root: Option<&Path>) -> io::Result<PathBuf> {
+            let entry_dest = read_link(path)?;
+            if let Some(root) = target_root
+                && entry_dest.has_root()
+            {
+                Ok(root.join(without_root(&entry_dest)))
+            } else if entry_dest.is_relative() {
+                // Bare names have an empty parent
+                Ok(match path.parent() {
+                    Some(parent) if !parent.as_os_str().is_empty() => parent.join(entry_dest),
+                    _ => env::current_dir()?.join(entry_dest),
+                })
+            } else {
+                Ok(entry_dest)
+            }
+        }
+
+        fn inner_resolve_symlink(
+            path: &PathBuf,
+            options: &Options,
+            target_root: Option<&Path>,
+        ) -> io::Result<Option<PathBuf>> {
+            // Same as the usual limit on Linux
+            const MAX_LOGICAL_HOPS: usize = 40;
+
+            if !path.symlink_metadata()?.is_symlink() {
+                return Ok(None);
+            }
+            let entry_dest = link_target(path, target_root)?;
+
+            Ok(Some(if options.no_canonicalize {
+                path::absolute(entry_dest)?
+            } else if options.logical {
+                let mut dest = normalize_lexically(&path::absolute(entry_dest)?);
+                for _ in 0..MAX_LOGICAL_HOPS {
+                    if !dest.is_symlink() {
+                        // Fails the same as a physical link to nothing
+                        if !dest.try_exists()? {
+                            return Err(io::Error::from(ErrorKind::NotFound));
+                        }
+                        return Ok(Some(dest));
+                    }
+                    dest = normalize_lexically(&path::absolute(link_target(&dest, target_root)?)?);
+                }
+                return Err(io::Error::other(format!(
+                    "More than {MAX_LOGICAL_HOPS} links from {path:?}"
+                )));
+            } else if let Some(root) = target_root {
+                canonicalize_under(root, path)?
+            } else {
+                canonicalize(entry_dest)?
+            }))
+        }
+
+        if let Some(cycle) = symlink_cycle(path) {
+            if cycle.len() == 1 && link_location(path).as_ref() == cycle.first() {
+                self.log(Action::SkipSelfReference(path));
+            } else {
+                self.log(Action::SkipCycle(path));
+            }
+            return Ok(None);
+        }
+
+        match inner_resolve_symlink(path, self.options, self.target_root.as_deref()) {
+            Ok(x) => {
+                if let Some(dest) = &x {
+                    self.log_verbose(Action::Resolved { link: path, dest });
+                    self.log_chain(path);
+                }
+                Ok(x)
+            }
+            // Ignore FilesystemLoop errors caused by self-referential symbolic
+            // links.
+            Err(e) if is_filesystem_loop(&e) => {
+                self.log(Action::SkipSelfReference(path));
+                Ok(None)
+            }
+            // Broken links are reported once they are relinked.
+            Err(e) if e.kind() == ErrorKind::NotFound && path.is_symlink() => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Follows the chain of links starting at `path`, returning the links that
+/// loop back on each other if the chain never reaches a non-link.
+fn symlink_cycle(path: &Path) -> Option<Vec<PathBuf>> {
+    let mut chain = Vec::new();
+    let mut visited = HashSet::new();
+    let mut current = link_location(path)?;
+
+    while current.symlink_metadata().ok()?.is_symlink() {
+        if !visited.insert(current.clone()) {
+            let start = chain.iter().position(|link| *link == current)?;
+            return Some(chain.split_off(start));
+        }
+
+        let target = read_link(&current).ok()?;
+        let next = match current.parent() {
+            Some(parent) => parent.join(target),
+            None => target,
+        };
+        chain.push(current);
+        current = link_location(&next)?;
+    }
+
+    None
+}
+
+/// Canonical location of the link at `path`, without following the link
+/// itself.
+fn link_location(path: &Path) -> Option<PathBuf> {
+    let path = path::absolute(path).ok()?;
+    Some(canonicalize(path.parent()?).ok()?.join(path.file_name()?))
+}
+
+/// Absolute form of the input `path`, decided before anything is resolved.
+///
+/// [`path::absolute`] keeps `..` as is, so `../link` from inside the
+/// directory `link` points to would still start with that directory.
+/// Resolving the parent instead keeps the input itself unresolved.
+fn absolute_input(path: &Path) -> io::Result<PathBuf> {
+    let absolute = path::absolute(path)?;
+    if absolute
+        .components()
+        .any(|component| component == Component::ParentDir)
+    {
+        Ok(link_location(&absolute).unwrap_or(absolute))
+    } else {
+        Ok(absolute)
+    }
+}
+
+/// `path` without any drive or root, relative to wherever it started.
+fn without_root(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| !matches!(component, Component::Prefix(_) | Component::RootDir))
+        .collect()
+}
+
+/// Canonical destination of the link at `path`, following absolute links
+/// from inside of the canonical `root` as if it were `/`, for
+/// [`Options::target_root`].
+///
+/// The directory of `path` is canonicalized as usual. Once inside of `root`,
+/// `..` never leaves it.
+fn canonicalize_under(root: &Path, path: &Path) -> io::Result<PathBuf> {
+    // Same as the usual limit on Linux
+    const MAX_HOPS: usize = 40;
+
+    let components = |path: &Path| -> Vec<PathBuf> {
+        path.components()
+            .rev()
+            .map(|component| component.as_os_str().into())
+            .collect()
+    };
+    let path = path::absolute(path)?;
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return canonicalize(path);
+    };
+    // Popped from the end, in path order
+    let mut pending = vec![PathBuf::from(name)];
+    let mut resolved = canonicalize(dir)?;
+    let mut hops = 0;
+    while let Some(next) = pending.pop() {
+        match next.components().next() {
+            Some(Component::ParentDir) if resolved != root => {
+                resolved.pop();
+            }
+            Some(Component::Normal(name)) => {
+                let candidate = resolved.join(name);
+                // Fails the same as a physical link to nothing
+                if !candidate.symlink_metadata()?.is_symlink() {
+                    resolved = candidate;
+                    continue;
+                }
+
+                hops += 1;
+                if hops > MAX_HOPS {
+                    return Err(io::Error::other(format!(
+                        "More than {MAX_HOPS} links from {path:?}"
+                    )));
+                }
+                let target = read_link(&candidate)?;
+                if target.has_root() {
+                    resolved = root.to_path_buf();
+                    pending.extend(components(&without_root(&target)));
+                } else {
+                    pending.extend(components(&target));
+                }
+            }
+            Some(component @ (Component::Prefix(_) | Component::RootDir)) => {
+                resolved.push(component);
+            }
+            Some(Component::ParentDir | Component::CurDir) | None => (),
+        }
+    }
+    Ok(resolved)
+}
+
+/// Checks if the link at `path` points straight to another link, without
+/// following that one.
+fn points_to_link(path: &Path) -> io::Result<bool> {
+    let target = read_link(path)?;
+    let target = match path.parent() {
+        Some(dir) => dir.join(target),
+        None => target,
+    };
+    Ok(target
+        .symlink_metadata()
+        .is_ok_and(|metadata| metadata.is_symlink()))
+}
+
+/// Absolute target of the link at `path`, as stored in the link.
+///
+/// Relative targets are joined to the canonical directory of the link, and
+/// nothing else is resolved, so this works for broken links.
+fn stored_target(path: &Path) -> io::Result<PathBuf> {
+    let target = read_link(path)?;
+    match link_location(path).as_deref().and_then(Path::parent) {
+        Some(dir) => Ok(normalize_lexically(&dir.join(target))),
+        None => path::absolute(target),
+    }
+}
+
+/// Removes every `.` from the absolute `path`, and every `..` along with the
+/// component before it, without looking at the filesystem.
+fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Path to `target` from inside of `dir`, when both are canonical.
+///
+/// There is no relative path between different Windows drives.
+fn relative_path(dir: &Path, target: &Path) -> Option<PathBuf> {
+    let (mut dir, mut target) = (dir.components().peekable(), target.components().peekable());
+    if dir.peek() != target.peek() {
+        return None;
+    }
+    while dir.peek().is_some() && dir.peek() == target.peek() {
+        dir.next();
+        target.next();
+    }
+
+    let relative: PathBuf = dir.map(|_| Component::ParentDir).chain(target).collect();
+    Some(if relative.as_os_str().is_empty() {
+        Component::CurDir.as_os_str().into()
+    } else {
+        relative
+    })
+}
+
+/// Path to `path` from inside of `base` for logging, or `path` as is if there
+/// is none.
+fn relative_display(base: &Path, path: &Path) -> PathBuf {
+    relative_path(base, path).unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Checks if `e` was caused by too many levels of links.
+fn is_filesystem_loop(e: &io::Error) -> bool {
+    // ELOOP, or ERROR_CANT_RESOLVE_FILENAME on Windows
+    #[cfg(windows)]
+    const ERROR_CANT_RESOLVE_FILENAME: i32 = 1921;
+    #[cfg(unix)]
+    const FILESYSTEM_LOOP: Option<i32> = Some(libc::ELOOP);
+    #[cfg(windows)]
+    const FILESYSTEM_LOOP: Option<i32> = Some(ERROR_CANT_RESOLVE_FILENAME);
+    #[cfg(not(any(unix, windows)))]
+    const FILESYSTEM_LOOP: Option<i32> = None;
+
+    FILESYSTEM_LOOP.is_some() && e.raw_os_error() == FILESYSTEM_LOOP
+}
+
+/// Checks if `e` was caused by a path, or a name in it, that is longer than
+/// the platform allows.
+fn is_path_too_long(e: &io::Error) -> bool {
+    use std::error::Error as _;
+
+    // ENAMETOOLONG, or ERROR_FILENAME_EXCED_RANGE on Windows
+    #[cfg(unix)]
+    const NAME_TOO_LONG: Option<i32> = Some(libc::ENAMETOOLONG);
+    #[cfg(windows)]
+    const NAME_TOO_LONG: Option<i32> = Some(206);
+    #[cfg(not(any(unix, windows)))]
+    const NAME_TOO_LONG: Option<i32> = None;
+
+    // Errors that say which path failed keep the original inside
+    let e = DelinkError::from_io(e)
+        .and_then(|e| e.source()?.downcast_ref())
+        .unwrap_or(e);
+    NAME_TOO_LONG.is_some() && e.raw_os_error() == NAME_TOO_LONG
+}
+
+/// Returns the canonical form of `path`.
+#[cfg(unix)]
+fn canonicalize<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
+    path.as_ref().canonicalize()
+}
+
+/// Returns the canonical form of `path`.
+///
+/// Local drive paths are returned without the verbatim `\\?\` prefix, to
+/// match the paths from [`path::absolute`].
+#[cfg(windows)]
+fn canonicalize<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
+    let canonical = path.as_ref().canonicalize()?;
+    let local = canonical
+        .to_str()
+        .and_then(|canonical| canonical.strip_prefix(r"\\?\"))
+        .filter(|local| local.as_bytes().get(1) == Some(&b':'))
+        .map(PathBuf::from);
+    Ok(local.unwrap_or(canonical))
+}
+
+/// Creates a link at `link` pointing to `original`.
+#[cfg(unix)]
+fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(original: P, link: Q) -> io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+/// Creates a link at `link` pointing to `original`.
+///
+/// Windows distinguishes between links to files and links to directories, so
+/// the type is taken from what `original` currently points to.
+#[cfg(windows)]
+fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(original: P, link: Q) -> io::Result<()> {
+    let (original, link) = (original.as_ref(), link.as_ref());
+    let target = match link.parent() {
+        Some(parent) => parent.join(original),
+        None => original.to_path_buf(),
+    };
+    if target.is_dir() {
+        std::os::windows::fs::symlink_dir(original, link)
+    } else {
+        std::os::windows::fs::symlink_file(original, link)
+    }
+}
+
+fn maybe_remove_file<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    match remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        // Windows removes links to directories as directories
+        #[cfg(windows)]
+        Err(_)
+            if path
+                .symlink_metadata()
+                .is_ok_and(|metadata| metadata.file_type().is_symlink_dir()) =>
+        {
+            fs::remove_dir(path)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// [`fs::read_link`], with `path` in any error.
+fn read_link(path: &Path) -> io::Result<PathBuf> {
+    fs::read_link(path).map_err(|source| {
+        DelinkError::ReadLink {
+            path: path.to_path_buf(),
+            source,
+        }
+        .into()
+    })
+}
+
+/// [`fs::read_dir`], with `path` in any error.
+fn read_dir(path: &Path) -> io::Result<fs::ReadDir> {
+    fs::read_dir(path).map_err(|source| {
+        DelinkError::ReadDir {
+            path: path.to_path_buf(),
+            source,
+        }
+        .into()
+    })
+}
+
+/// [`fs::create_dir`], with `path` in any error.
+fn create_dir(path: &Path) -> io::Result<()> {
+    fs::create_dir(path).map_err(|source| {
+        DelinkError::CreateDir {
+            path: path.to_path_buf(),
+            source,
+        }
+        .into()
+    })
+}
+
+/// Error for copying `src` to replace the link at `dest`.
+fn copy_error(src: &Path, dest: &Path, source: io::Error) -> io::Error {
+    DelinkError::Copy {
+        src: src.to_path_buf(),
+        dest: dest.to_path_buf(),
+        source,
+    }
+    .into()
+}
+
+/// Copies `src` to `dest`, keeping the exact mode of `src`.
+///
+/// [`fs::copy`] may drop special mode bits (setuid, setgid, sticky), so the
+/// permissions are always applied again after copying.
+fn copy_file(src: &Path, dest: &Path) -> io::Result<u64> {
+    let permissions = src.metadata()?.permissions();
+    let bytes = fs::copy(src, dest)?;
+    fs::set_permissions(dest, permissions)?;
+    Ok(bytes)
+}
+
+/// Clones `src` to `dest` with `FICLONE`, keeping the exact mode of `src`.
+///
+/// Nothing is left at `dest` if cloning fails.
+#[cfg(target_os = "linux")]
+fn reflink_file(src: &Path, dest: &Path) -> io::Result<u64> {
+    use std::os::fd::AsRawFd;
+
+    let src_file = File::open(src)?;
+    let metadata = src_file.metadata()?;
+    let dest_file = File::create(dest)?;
+    // SAFETY: Both descriptors stay open for the whole call.
+    let result = unsafe { libc::ioctl(dest_file.as_raw_fd(), libc::FICLONE, src_file.as_raw_fd()) };
+    if result != 0 {
+        let e = io::Error::last_os_error();
+        drop(dest_file);
+        let _ = remove_file(dest);
+        return Err(e);
+    }
+    fs::set_permissions(dest, metadata.permissions())?;
+    Ok(metadata.len())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink_file(_src: &Path, _dest: &Path) -> io::Result<u64> {
+    Err(io::Error::from(ErrorKind::Unsupported))
+}
+
+/// Checks if the files at `a` and `b` have identical contents.
+fn same_contents(a: &Path, b: &Path) -> io::Result<bool> {
+    const CHUNK: usize = 64 * 1024;
+
+    let len = a.metadata()?.len();
+    if len != b.metadata()?.len() {
+        return Ok(false);
+    }
+
+    let (mut a, mut b) = (File::open(a)?, File::open(b)?);
+    let (mut a_buf, mut b_buf) = (vec![0; CHUNK], vec![0; CHUNK]);
+    let mut remaining = len;
+    while remaining > 0 {
+        let n = remaining.min(CHUNK as u64) as usize;
+        a.read_exact(&mut a_buf[..n])?;
+        b.read_exact(&mut b_buf[..n])?;
+        if a_buf[..n] != b_buf[..n] {
+            return Ok(false);
+        }
+        remaining -= n as u64;
+    }
+    Ok(true)
+}
+
+/// Checks if `path` is something other than a file or directory, such as a
+/// FIFO, socket, or device file.
+fn is_special(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|metadata| !metadata.is_file() && !metadata.is_dir())
+}
+
+/// Creates a node at `dest` of the same type, mode, and device as `src`.
+#[cfg(unix)]
+fn make_node(src: &Path, dest: &Path) -> io::Result<()> {
+    use std::{
+        ffi::CString,
+        os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    };
+
+    let metadata = src.metadata()?;
+    let dest = CString::new(dest.as_os_str().as_bytes())?;
+    // SAFETY: `dest` is NUL terminated, as `mknod` expects.
+    let result = unsafe {
+        libc::mknod(
+            dest.as_ptr(),
+            metadata.mode() as libc::mode_t,
+            metadata.rdev() as libc::dev_t,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(unix))]
+fn make_node(_src: &Path, _dest: &Path) -> io::Result<()> {
+    Err(io::Error::from(ErrorKind::Unsupported))
+}
+
+/// Sets the access and modification times of `dest` to those of `src`.
+fn copy_times(src: &Path, dest: &Path) -> io::Result<()> {
+    let metadata = src.metadata()?;
+    let times = FileTimes::new()
+        .set_accessed(metadata.accessed()?)
+        .set_modified(metadata.modified()?);
+    File::options().write(true).open(dest)?.set_times(times)
+}
+
+/// Sets the access and modification times of the link itself at `link` to
+/// those in `metadata`.
+#[cfg(unix)]
+fn set_link_times(link: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+    use std::{
+        ffi::CString,
+        os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    };
+
+    let link = CString::new(link.as_os_str().as_bytes())?;
+    let times = [
+        libc::timespec {
+            tv_sec: metadata.atime() as _,
+            tv_nsec: metadata.atime_nsec() as _,
+        },
+        libc::timespec {
+            tv_sec: metadata.mtime() as _,
+            tv_nsec: metadata.mtime_nsec() as _,
+        },
+    ];
+    // SAFETY: `link` is NUL terminated and `times` holds both timestamps, as
+    // `utimensat` expects.
+    let result = unsafe {
+        libc::utimensat(
+            libc::AT_FDCWD,
+            link.as_ptr(),
+            times.as_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Sets the access and modification times of the link itself at `link` to
+/// those in `metadata`.
+#[cfg(windows)]
+fn set_link_times(link: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    // FILE_FLAG_OPEN_REPARSE_POINT opens the link instead of its target, and
+    // FILE_FLAG_BACKUP_SEMANTICS allows links to directories
+    const FLAGS: u32 = 0x0020_0000 | 0x0200_0000;
+
+    let times = FileTimes::new()
+        .set_accessed(metadata.accessed()?)
+        .set_modified(metadata.modified()?);
+    File::options()
+        .write(true)
+        .custom_flags(FLAGS)
+        .open(link)?
+        .set_times(times)
+}
+
+/// Sets every extended attribute of `src` on `dest`.
+#[cfg(target_os = "linux")]
+fn copy_xattrs(src: &Path, dest: &Path) -> io::Result<()> {
+    for name in xattr::list(src)? {
+        if let Some(value) = xattr::get(src, &name)? {
+            xattr::set(dest, &name, &value)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn copy_xattrs(_src: &Path, _dest: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Sets the owning user and group of `src` on `dest`.
+#[cfg(unix)]
+fn copy_owner(src: &Path, dest: &Path) -> io::Result<()> {
+    let metadata = src.metadata()?;
+    std::os::unix::fs::chown(dest, Some(metadata.uid()), Some(metadata.gid()))
+}
+
+#[cfg(not(unix))]
+fn copy_owner(_src: &Path, _dest: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Checks if the link at `path` itself is owned by the effective user.
+#[cfg(unix)]
+fn owned_by_user(path: &Path) -> io::Result<bool> {
+    // SAFETY: Only reads the effective user of this process.
+    let euid = unsafe { libc::geteuid() };
+    Ok(path.symlink_metadata()?.uid() == euid)
+}
+
+#[cfg(not(unix))]
+fn owned_by_user(_path: &Path) -> io::Result<bool> {
+    Ok(true)
+}
+
+/// Gives `dest` the permissions of a new file under `umask`, with the
+/// execute bits of `src`.
+#[cfg(unix)]
+fn copy_exec_bits(src: &Path, dest: &Path, umask: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let exec = src.metadata()?.permissions().mode() & 0o111;
+    fs::set_permissions(dest, fs::Permissions::from_mode((0o666 & !umask) | exec))
+}
+
+#[cfg(not(unix))]
+fn copy_exec_bits(_src: &Path, _dest: &Path, _umask: u32) -> io::Result<()> {
+    Ok(())
+}
+
+/// File mode creation mask of this process.
+///
+/// Only read without changing it on Linux. Elsewhere the mask is briefly
+/// cleared, so this has to run before any other thread creates files.
+#[cfg(unix)]
+fn umask() -> u32 {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if let Some(mask) = fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            let line = status
+                .lines()
+                .find_map(|line| line.strip_prefix("Umask:"))?;
+            u32::from_str_radix(line.trim(), 8).ok()
+        })
+    {
+        return mask;
+    }
+
+    // SAFETY: `umask` cannot fail, and the original mask is put straight
+    // back.
+    let mask = unsafe {
+        let mask = libc::umask(0);
+        libc::umask(mask);
+        mask
+    };
+    // Narrower than `u32` on some platforms
+    mask as u32
+}
+
+#[cfg(not(unix))]
+fn umask() -> u32 {
+    0
+}
+
+/// Checks if `src` is on the same filesystem that `dest` would be created on.
+#[cfg(unix)]
+fn same_filesystem(src: &Path, dest: &Path) -> io::Result<bool> {
+    // The closest existing directory decides the filesystem, since `dest`
+    // may be inside of a directory that is not yet created.
+    let dest_dev = dest
+        .ancestors()
+        .skip(1)
+        .find_map(|dir| dir.metadata().ok())
+        .map(|metadata| metadata.dev());
+    Ok(Some(src.metadata()?.dev()) == dest_dev)
+}
+
+/// Checks if `src` is on the same volume that `dest` would be created on.
+#[cfg(windows)]
+fn same_filesystem(src: &Path, dest: &Path) -> io::Result<bool> {
+    // Volume serial numbers are not exposed on stable, so compare the drive
+    // or share of the closest existing directory instead.
+    let dest_prefix = dest
+        .ancestors()
+        .skip(1)
+        .find_map(|dir| canonicalize(dir).ok())
+        .and_then(|dir| dir.components().next().map(|c| c.as_os_str().to_owned()));
+    let src_prefix = canonicalize(src)?
+        .components()
+        .next()
+        .map(|c| c.as_os_str().to_owned());
+    Ok(src_prefix == dest_prefix)
+}
+
+/// Hard links `src` to `dest`, copying if they end up on different
+/// filesystems.
+fn hard_link_file(src: &Path, dest: &Path) -> io::Result<()> {
+    match fs::hard_link(src, dest) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => copy_file(src, dest).map(|_| ()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Replaces `path` with the contents of `symlink_dest`, or walks `path` for
+/// links to replace if it is a directory.
+///
+/// `symlink_dest` is expected to come from [`resolve_symlink`].
+pub fn relink<W: Write>(
+    // Absolute but not canonical path
+    path: &Path,
+    // Canonical path
+    symlink_dest: Option<PathBuf>,
+    writer: &mut W,
+    options: &Options,
+) -> io::Result<()> {
+    let mut ctx = Context::new(writer, options).prepare()?;
+    if options.move_files {
+        ctx.references = link_references(&[path.to_path_buf()]);
+    }
+    let result = ctx.relink(path.to_path_buf(), symlink_dest, 0);
+    ctx.finish_progress();
+    result
+}
+
+impl Work {
+    /// The path this step acts on.
+    fn path(&self) -> &Path {
+        match self {
+            Self::Visit { path, .. }
+            | Self::Relink { path, .. }
+            | Self::Commit { path, .. }
+            | Self::SetPermissions { path, .. } => path,
+        }
+    }
+}
+
+/// Pending step of a [`Context::relink`] traversal.
+enum Work {
+    /// Resolve a directory entry, then relink it.
+    Visit { path: PathBuf, depth: usize },
+    /// Relink a path with an already resolved destination.
+    Relink {
+        path: PathBuf,
+        symlink_dest: Option<PathBuf>,
+        depth: usize,
+    },
+    /// Replace a link with its fully populated staging directory.
+    Commit { temp: PathBuf, path: PathBuf },
+    /// Apply permissions to a directory once it is fully populated.
+    SetPermissions {
+        path: PathBuf,
+        permissions: fs::Permissions,
+    },
+}
+
+impl<W: Write> Context<'_, W> {
+    /// Walks everything under `path` with an explicit stack, so arbitrarily
+    /// deep trees cannot overflow the call stack.
+    fn relink(
+        &mut self,
+        // Absolute but not canonical path
+        path: PathBuf,
+        // Canonical path
+        symlink_dest: Option<PathBuf>,
+        // Number of directories descended from the input path
+        depth: usize,
+    ) -> io::Result<()> {
+        self.walk(vec![Work::Relink {
+            path,
+            symlink_dest,
+            depth,
+        }])
+    }
+
+    /// Copies the input `path` into [`Options::into`], resolving every link
+    /// along the way.
+    fn mirror(&mut self, path: &Path) -> io::Result<()> {
+        let source = absolute_input(path)?;
+        let Some(target) = self.mirror_target(&source) else {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("{source:?} has no name to copy into"),
+            ));
+        };
+        if target.starts_with(canonicalize(&source)?) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Cannot copy {source:?} into itself at {target:?}"),
+            ));
+        }
+        if target.symlink_metadata().is_ok() && !self.options.merge {
+            return Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{target:?} already exists"),
+            ));
+        }
+        if !self.options.dry_run
+            && let Some(into) = target.parent()
+        {
+            fs::create_dir_all(into)?;
+        }
+
+        // Built off to the side like a populated directory, so a failure
+        // leaves nothing behind
+        let temp = staging_path(&target);
+        self.staging = Some((temp.clone(), target.clone()));
+        self.walk(vec![
+            Work::Commit {
+                temp,
+                path: target.clone(),
+            },
+            Work::Relink {
+                path: target,
+                symlink_dest: Some(source),
+                depth: 0,
+            },
+        ])
+    }
+
+    /// Where the input `path` is copied to with [`Options::into`].
+    fn mirror_target(&self, path: &Path) -> Option<PathBuf> {
+        Some(self.into.as_ref()?.join(path.file_name()?))
+    }
+
+    /// Works through `stack`, removing any partly populated directory if
+    /// something fails.
+    fn walk(&mut self, stack: Vec<Work>) -> io::Result<()> {
+        let result = self.run(stack);
+        if result.is_err() {
+            // Leave the original link intact
+            self.populating.clear();
+            self.flattened.clear();
+            if let Some((temp, _)) = self.staging.take()
+                && !self.options.dry_run
+            {
+                // Copies of single files are staged too, with Options::into
+                let _ = fs::remove_dir_all(&temp).or_else(|_| remove_file(&temp));
+            }
+        }
+        result
+    }
+
+    /// Works through `stack` until it is empty, something fails, or
+    /// [`Options::limit`] is reached.
+    fn run(&mut self, mut stack: Vec<Work>) -> io::Result<()> {
+        if self.options.breadth_first {
+            return self.run_breadth_first(stack);
+        }
+
+        while !self.stopped
+            && let Some(work) = stack.pop()
+        {
+            self.try_step(work, &mut stack)?;
+        }
+
+        Ok(())
+    }
+
+    /// Works through `stack` one directory level at a time, for
+    /// [`Options::breadth_first`].
+    ///
+    /// Only one directory can be staged at once, so each populated directory
+    /// is walked in its own queue, then committed before going back to the
+    /// queue it came from.
+    fn run_breadth_first(&mut self, stack: Vec<Work>) -> io::Result<()> {
+        // Entries to walk, and the steps to finish each queue with
+        let mut frames = vec![(stack.into_iter().rev().collect::<VecDeque<_>>(), Vec::new())];
+        while !self.stopped
+            && let Some((queue, finish)) = frames.last_mut()
+        {
+            let Some(work) = queue.pop_front().or_else(|| finish.pop()) else {
+                frames.pop();
+                continue;
+            };
+
+            let mut pushed = Vec::new();
+            self.try_step(work, &mut pushed)?;
+            let (finishing, mut entries): (Vec<_>, Vec<_>) = pushed.into_iter().partition(|work| {
+                matches!(work, Work::Commit { .. } | Work::SetPermissions { .. })
+            });
+            // Pushed to be popped in directory order
+            entries.reverse();
+            if finishing
+                .iter()
+                .any(|work| matches!(work, Work::Commit { .. }))
+            {
+                frames.push((entries.into(), finishing));
+            } else {
+                queue.extend(entries);
+                finish.extend(finishing);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Takes a single `work` step, only skipping entries that cannot be
+    /// accessed or have paths that are too long.
+    fn try_step(&mut self, work: Work, stack: &mut Vec<Work>) -> io::Result<()> {
+        let work_path = work.path().to_path_buf();
+        match self.step(work, stack) {
+            // Populated directories can nest deeper than anything they came
+            // from
+            Err(e) if is_path_too_long(&e) => {
+                self.log(Action::SkipPathTooLong(&work_path));
+                Ok(())
+            }
+            // Only this entry is skipped, unless the run stops
+            Err(e)
+                if e.kind() == ErrorKind::PermissionDenied
+                    && self.options.on_error != OnError::Abort =>
+            {
+                self.handle_error(&work_path, e)
+            }
+            result => result,
+        }
+    }
+
+    /// Relinks the `entries` of the directory being populated on `jobs`
+    /// threads.
+    ///
+    /// Each entry is logged into its own buffer, which is written out in
+    /// order, as if the entries were relinked one at a time.
+    fn relink_parallel(&mut self, entries: Vec<Work>, jobs: usize) -> io::Result<()> {
+        let queue = Mutex::new(entries.into_iter().enumerate());
+        let (sender, receiver) = mpsc::channel();
+        let (options, copy, before_use) = (self.options, self.copy, self.before_use);
+        let umask = self.umask;
+        let (staging, root, populating) = (&self.staging, &self.root, &self.populating);
+        let (target_root, relative_to, excluded) =
+            (&self.target_root, &self.relative_to, &self.excluded);
+        let journals = (0..jobs)
+            .map(|_| self.journal.as_ref().map(Journal::try_clone).transpose())
+            .collect::<io::Result<Vec<_>>>()?;
+
+        thread::scope(|s| {
+            let workers: Vec<_> = journals
+                .into_iter()
+                .map(|journal| {
+                    let (queue, sender) = (&queue, sender.clone());
+                    s.spawn(move || -> Result<(), (io::Error, bool)> {
+                        let mut buffer = Vec::new();
+                        let mut ctx = Context::new(&mut buffer, options);
+                        ctx.copy = copy;
+                        ctx.before_use = before_use;
+                        ctx.umask = umask;
+                        // Reported from the calling thread instead
+                        ctx.progress = None;
+                        ctx.staging = staging.clone();
+                        ctx.journal = journal;
+                        ctx.root = root.clone();
+                        ctx.target_root = target_root.clone();
+                        ctx.relative_to = relative_to.clone();
+                        ctx.excluded = excluded.clone();
+                        ctx.populating = populating.clone();
+
+                        while let Some((idx, work)) = queue.lock().unwrap().next() {
+                            let path = work.path().to_path_buf();
+                            let result = ctx.run(vec![work]);
+                            let output = (
+                                idx,
+                                path,
+                                mem::take(ctx.writer),
+                                mem::take(&mut ctx.summary),
+                                mem::take(&mut ctx.kept),
+                                mem::take(&mut ctx.processed),
+                                mem::take(&mut ctx.manifest),
+                                mem::take(&mut ctx.targets),
+                            );
+                            if sender.send(output).is_err() {
+                                break;
+                            }
+                            if let Err(e) = result {
+                                // Stops the other threads after their current entry
+                                queue.lock().unwrap().by_ref().for_each(drop);
+                                return Err((e, ctx.aborted));
+                            }
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            drop(sender);
+
+            let mut finished = HashMap::new();
+            let mut next = 0;
+            for (idx, path, output, summary, kept, processed, manifest, targets) in receiver {
+                self.summary.merge(summary);
+                self.kept.extend(kept);
+                self.processed += processed;
+                self.manifest.extend(manifest);
+                self.targets.extend(targets);
+                if let Some(progress) = &mut self.progress {
+                    progress.update(&self.summary, self.processed, &path);
+                }
+                finished.insert(idx, output);
+                while let Some(output) = finished.remove(&next) {
+                    let _ = self.writer.write_all(&output);
+                    next += 1;
+                }
+            }
+            workers.into_iter().try_for_each(|worker| {
+                worker.join().unwrap().map_err(|(e, aborted)| {
+                    self.aborted |= aborted;
+                    e
+                })
+            })
+        })
+    }
+
+    /// Number of threads to populate a directory link with, if more than one.
+    fn tree_jobs(&self) -> Option<usize> {
+        self.options
+            .tree_jobs
+            .map(NonZeroUsize::get)
+            .filter(|&jobs| {
+                jobs > 1
+                    && self.staging.is_none()
+                    && !self.options.serial()
+                    && self.options.flatten.is_none()
+            })
+    }
+
+    fn step(&mut self, work: Work, stack: &mut Vec<Work>) -> io::Result<()> {
+        match work {
+            Work::Visit { path, depth } => {
+                let symlink_dest = self.resolve_symlink(&path)?;
+                self.relink_entry(path, symlink_dest, depth, stack)
+            }
+            Work::Relink {
+                path,
+                symlink_dest,
+                depth,
+            } => self.relink_entry(path, symlink_dest, depth, stack),
+            Work::Commit { temp, path } => {
+                self.staging = None;
+                self.populating.clear();
+                self.flattened.clear();
+                // Copies made with Options::into replace nothing
+                let mirrored = self.options.into.is_some();
+                if self.options.dry_run {
+                    return if mirrored {
+                        Ok(())
+                    } else {
+                        self.make_room(&path, Replacement::Populate)
+                    };
+                }
+
+                let committed = if mirrored {
+                    Ok(())
+                } else {
+                    self.make_room(&path, Replacement::Populate)
+                }
+                .and_then(|()| {
+                    if mirrored && self.options.merge && path.symlink_metadata().is_ok() {
+                        self.merge(&temp, &path)
+                    } else {
+                        fs::rename(&temp, &path)
+                    }
+                });
+                if committed.is_err() {
+                    // Copies of single files are staged too, with Options::into
+                    let _ = fs::remove_dir_all(&temp).or_else(|_| remove_file(&temp));
+                }
+                committed
+            }
+            Work::SetPermissions { path, permissions } => fs::set_permissions(path, permissions),
+        }
+    }
+
+    /// Relinks a single path, queueing up any entries below it.
+    fn relink_entry(
+        &mut self,
+        mut path: PathBuf,
+        symlink_dest: Option<PathBuf>,
+        depth: usize,
+        stack: &mut Vec<Work>,
+    ) -> io::Result<()> {
+        let options = self.options;
+        self.log_verbose(Action::Visit(&path));
+
+        if options.exclude_hidden
+            && self.staging.is_some()
+            && path
+                .file_name()
+                .is_some_and(|name| name.as_encoded_bytes().starts_with(b"."))
+        {
+            self.log(Action::SkipHidden(&path));
+            return Ok(());
+        }
+        if self.collides(&path)
+            // Flattened directories are never created, so cannot collide
+            && !(self.flattens(&path) && symlink_dest.as_ref().is_some_and(|dest| dest.is_dir()))
+        {
+            match options.flatten {
+                Some(Collision::Rename) => path = self.free_name(&path),
+                Some(Collision::Skip) => {
+                    self.log(Action::SkipCollision(&path));
+                    return Ok(());
+                }
+                None => {
+                    self.log(Action::SkipCaseCollision(&path));
+                    return Ok(());
+                }
+            }
+        }
+
+        // Only links in the original tree are filtered, not the contents of
+        // populated directories.
+        if symlink_dest.is_some() && self.staging.is_none() && !self.filter_matches(&path) {
+            self.log(Action::SkipFilter(&path));
+            return Ok(());
+        }
+        if let Some(limit) = options.limit
+            && symlink_dest.is_some()
+            && self.staging.is_none()
+            && self.replaced >= limit
+        {
+            self.log(Action::StopLimit(&path));
+            self.stopped = true;
+            return Ok(());
+        }
+        if let Some(newer_than) = options.newer_than
+            && symlink_dest.is_some()
+            && self.staging.is_none()
+            && path.symlink_metadata()?.modified()? < newer_than
+        {
+            self.log(Action::SkipOld(&path));
+            return Ok(());
+        }
+        if options.mine_only
+            && symlink_dest.is_some()
+            && self.staging.is_none()
+            && !owned_by_user(&path)?
+        {
+            self.log(Action::SkipNotOwner(&path));
+            return Ok(());
+        }
+        if options.require_concrete
+            && symlink_dest.is_some()
+            && self.staging.is_none()
+            && points_to_link(&path)?
+        {
+            self.log(Action::SkipIndirect(&path));
+            return Ok(());
+        }
+
+        // Links inside of a populated directory are kept as links.
+        if options.shallow
+            && self.staging.is_some()
+            && let Some(dest) = &symlink_dest
+            && dest.is_symlink()
+        {
+            let target = read_link(dest)?;
+            self.log(Action::Symlink {
+                target: &target,
+                link: &path,
+            });
+            if !options.dry_run {
+                self.create_link(&target, &self.staged(&path), self.link_times(dest)?)?;
+            }
+            return Ok(());
+        }
+
+        // Only two relevant entries are directories and symlinks.
+        if let Some(dest) = symlink_dest {
+            if dest == path {
+                self.log(Action::SkipSelfReference(&dest));
+            } else if self.staging.is_some()
+                && dest.is_symlink()
+                && let Some(cycle) = symlink_cycle(&dest)
+            {
+                // Links in the source directory are not resolved beforehand
+                if cycle.len() == 1 && link_location(&dest).as_ref() == cycle.first() {
+                    self.log(Action::SkipSelfReference(&path));
+                } else {
+                    self.log(Action::SkipCycle(&path));
+                }
+                if !options.dry_run {
+                    self.create_link(
+                        &read_link(&dest)?,
+                        &self.staged(&path),
+                        self.link_times(&dest)?,
+                    )?;
+                }
+            } else if !dest.exists() {
+                // Only a link in a populated directory, or a single hop, can
+                // be broken here
+                self.broken_link(&path, &dest)?;
+            } else if options.only_broken && self.staging.is_none() {
+                self.log(Action::SkipValid(&path));
+            } else if let Some(root) = &self.root
+                && !canonicalize(&dest)?.starts_with(root)
+            {
+                self.log(Action::SkipOutsideRoot(&dest));
+                self.keep(&path);
+                // Left as a link inside of a populated directory too
+                if !options.dry_run && self.staging.is_some() {
+                    self.create_link(
+                        &read_link(&dest)?,
+                        &self.staged(&path),
+                        self.link_times(&dest)?,
+                    )?;
+                }
+            } else if options.relativize && self.staging.is_none() {
+                self.relativize(&path, &dest)?;
+            } else if options.absolutize && self.staging.is_none() {
+                self.absolutize(&path, &dest)?;
+            } else if options.no_canonicalize && self.staging.is_none() && dest.is_symlink() {
+                let mut target = read_link(&dest)?;
+                if target.is_relative()
+                    && let Some(parent) = dest.parent()
+                {
+                    target = parent.join(target);
+                }
+                self.log(Action::Symlink {
+                    target: &target,
+                    link: &path,
+                });
+                self.replace_link(&path, &target)?;
+            } else if options.merge
+                && self.staging.is_some()
+                && dest.is_file()
+                && !path.is_symlink()
+                && path.is_file()
+                && same_contents(&dest, &path)?
+            {
+                // Already copied by an earlier run, so nothing is staged
+                self.log(Action::SkipUnchanged(&path));
+            } else if is_special(&dest) && !(options.recreate_special && cfg!(unix)) {
+                self.log(Action::SkipSpecial(&path));
+            } else if options.dirs_only
+                && dest.is_file()
+                && (self.staging.is_none() || dest.is_symlink())
+            {
+                self.log(Action::SkipFileLink(&path));
+                if !options.dry_run && self.staging.is_some() {
+                    symlink(&dest, self.staged(&path))?;
+                }
+            } else if dest.is_file() && !options.extension_allowed(&dest) {
+                self.log(Action::SkipExt(&path));
+                // Left as a link inside of a populated directory too
+                if !options.dry_run && self.staging.is_some() {
+                    symlink(&dest, self.staged(&path))?;
+                }
+            } else if let Some(limit) = options.skip_larger_than
+                && dest.is_file()
+                && dest.metadata()?.len() > limit
+            {
+                self.log(Action::SkipLarge(&path));
+                // Left as a link inside of a populated directory too
+                if !options.dry_run && self.staging.is_some() {
+                    symlink(&dest, self.staged(&path))?;
+                }
+            } else if options.no_clobber
+                && options.merge
+                && self.staging.is_some()
+                && dest.is_file()
+                && path
+                    .symlink_metadata()
+                    .is_ok_and(|metadata| !metadata.is_dir())
+            {
+                // Nothing is staged, so merging keeps the existing file
+                self.log(Action::SkipExists(&path));
+            } else if !self.confirm(&path, &dest)? {
+                self.log(Action::SkipDeclined(&path));
+            } else if self.vanished(&dest) {
+                self.log(Action::SkipVanished(&dest));
+            } else if is_special(&dest) {
+                self.log(Action::Mknod {
+                    dest: &dest,
+                    link: &path,
+                });
+                let staged = self.staged(&path);
+                self.make_room(&staged, Replacement::Copy)?;
+                if !options.dry_run {
+                    make_node(&dest, &staged)?;
+                }
+            } else if dest.is_file() {
+                // Entries in a populated directory may still be links
+                let target = canonicalize(&dest)?;
+                let staged = self.staged(&path);
+                if options.move_files && self.staging.is_none() && same_filesystem(&dest, &path)? {
+                    if self.shared(&dest) {
+                        self.warn(&dest, "Has other links to it, so it is copied instead");
+                    } else {
+                        self.log(Action::Move {
+                            dest: &dest,
+                            link: &path,
+                        });
+                        self.make_room(&path, Replacement::Move)?;
+                        if !options.dry_run {
+                            fs::rename(&dest, &path)?;
+                        }
+                        self.targets.insert(target);
+                        return Ok(());
+                    }
+                }
+
+                if options.hardlink && same_filesystem(&dest, &staged)? {
+                    self.log(Action::Hardlink {
+                        dest: &dest,
+                        link: &path,
+                    });
+                    self.make_room(&staged, Replacement::Hardlink)?;
+                    if !options.dry_run {
+                        hard_link_file(&dest, &staged)?;
+                    }
+                    self.targets.insert(target);
+                    return Ok(());
+                }
+
+                if let Some(first) = self.copied.get(&target).cloned() {
+                    self.log(Action::Hardlink {
+                        dest: &first,
+                        link: &path,
+                    });
+                    self.make_room(&staged, Replacement::Hardlink)?;
+                    if !options.dry_run {
+                        // The first copy may be gone, when its directory
+                        // failed to populate.
+                        fs::hard_link(self.staged(&first), &staged)
+                            .or_else(|_| self.retrying(|| (self.copy)(&dest, &staged)).map(drop))
+                            .map_err(|source| copy_error(&dest, &path, source))?;
+                    }
+                    self.targets.insert(target);
+                    return Ok(());
+                }
+
+                // Measured from the source, so dry runs report the same total
+                let bytes = dest.metadata()?.len();
+                if let Some(limit) = options.max_total_bytes
+                    && self.summary.bytes + bytes > limit
+                {
+                    self.log(Action::AbortByteLimit(&path));
+                    self.summary.errors += 1;
+                    self.aborted = true;
+                    let source = io::Error::other(format!("Over the limit of {limit} bytes"));
+                    return Err(copy_error(&dest, &path, source));
+                }
+                // Logged once the copy is measured instead
+                let timed = options.timing && !options.dry_run;
+                if !timed {
+                    self.log_copy(&dest, &path, None);
+                }
+                self.summary.bytes += bytes;
+                if options.dry_run || self.staging.is_some() {
+                    self.make_room(&staged, Replacement::Copy)?;
+                    if !options.dry_run {
+                        let start = Instant::now();
+                        self.copy_into(&dest, &staged)
+                            .map_err(|source| copy_error(&dest, &path, source))?;
+                        if timed {
+                            self.log_copy(&dest, &path, Some((bytes, start.elapsed())));
+                        }
+                    }
+                } else {
+                    // Copied beside the link first, so a failed copy leaves
+                    // the link in place
+                    let temp = staging_path(&path);
+                    let start = Instant::now();
+                    let copied = self
+                        .copy_into(&dest, &temp)
+                        .map_err(|source| copy_error(&dest, &path, source))
+                        .map(|()| {
+                            // Before the link is gone, for its target and diff
+                            if timed {
+                                self.log_copy(&dest, &path, Some((bytes, start.elapsed())));
+                            }
+                        })
+                        .and_then(|()| self.make_room(&path, Replacement::Copy))
+                        .and_then(|()| self.retrying(|| fs::rename(&temp, &path)));
+                    if copied.is_err() {
+                        let _ = remove_file(&temp);
+                    }
+                    copied?;
+                }
+                if options.dedup {
+                    self.copied.insert(target.clone(), path.clone());
+                }
+                self.targets.insert(target);
+            } else {
+                debug_assert!(dest.is_dir(), "{dest:?} NOT dir");
+
+                if path.starts_with(&dest) || self.populates_itself(&path, &dest)? {
+                    self.log(Action::SkipRecursive(&path));
+                    // Also kept reachable, like directories that are too deep
+                    if !options.dry_run && self.staging.is_some() {
+                        symlink(&dest, self.staged(&path))?;
+                    }
+                } else if self.too_deep(depth) {
+                    self.log(Action::SkipDepth(&path));
+                    // Keep the contents reachable inside of a populated
+                    // directory by linking to them.
+                    if !options.dry_run && self.staging.is_some() {
+                        symlink(&dest, self.staged(&path))?;
+                    }
+                } else if options.same_filesystem && !same_filesystem(&dest, &self.staged(&path))? {
+                    self.log(Action::SkipXdev(&dest));
+                    self.keep(&path);
+                    // Also kept reachable, like directories that are too deep
+                    if !options.dry_run && self.staging.is_some() {
+                        symlink(&dest, self.staged(&path))?;
+                    }
+                } else if self.flattens(&path) {
+                    // Already flattened, or being flattened above this
+                    let source = canonicalize(&dest)?;
+                    if !self.flattened.insert(source.clone()) {
+                        self.log(Action::SkipRecursive(&path));
+                        return Ok(());
+                    }
+                    self.targets.insert(source);
+                    if let Some(parent) = path.parent() {
+                        populate(stack, parent, &dest, depth)?;
+                    }
+                } else {
+                    let tree_jobs = self.tree_jobs();
+                    self.log(Action::Populate {
+                        dest: &dest,
+                        link: &path,
+                    });
+                    // Only links change the canonical path below them
+                    let source = match path.parent().and_then(|dir| self.populating.get(dir)) {
+                        Some(parent) if !dest.is_symlink() => {
+                            parent.join(dest.file_name().unwrap_or_default())
+                        }
+                        _ => canonicalize(&dest)?,
+                    };
+                    self.targets.insert(source.clone());
+                    self.populating.insert(path.clone(), source);
+                    if self.staging.is_some() {
+                        if !options.dry_run {
+                            create_dir(&self.staged(&path))?;
+                            self.keep_owner(&dest, &self.staged(&path))?;
+                        }
+                    } else {
+                        // Build the directory off to the side, so a failure
+                        // leaves the original link intact.
+                        let temp = staging_path(&path);
+                        if !options.dry_run {
+                            create_dir(&temp)?;
+                            self.keep_owner(&dest, &temp)?;
+                        }
+                        self.staging = Some((temp.clone(), path.clone()));
+                        stack.push(Work::Commit {
+                            temp,
+                            path: path.clone(),
+                        });
+                    }
+                    // Applied last, so a read-only directory can still be
+                    // populated.
+                    if options.preserve_dir_perms && !options.dry_run {
+                        stack.push(Work::SetPermissions {
+                            path: self.staged(&path).into_owned(),
+                            permissions: dest.metadata()?.permissions(),
+                        });
+                    }
+                    if let Some(jobs) = tree_jobs {
+                        let mut entries = Vec::new();
+                        populate(&mut entries, &path, &dest, depth)?;
+                        // Queued up in directory order instead of popping order
+                        entries.reverse();
+                        self.relink_parallel(entries, jobs)?;
+                    } else {
+                        populate(stack, &path, &dest, depth)?;
+                    }
+                }
+            }
+        } else if path.is_dir() {
+            if self.too_deep(depth) {
+                self.log(Action::SkipDepth(&path));
+                return Ok(());
+            }
+            if options.no_recurse && depth > 0 {
+                self.log_verbose(Action::SkipDepth(&path));
+                self.keep(&path);
+                return Ok(());
+            }
+
+            let entries = read_dir(&path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<io::Result<Vec<_>>>()?;
+            // Reversed to pop in directory order
+            stack.extend(entries.into_iter().rev().map(|entry_path| Work::Visit {
+                path: entry_path,
+                depth: depth + 1,
+            }));
+        } else if path.is_symlink()
+            && canonicalize(&path).is_err_and(|e| e.kind() == ErrorKind::NotFound)
+        {
+            // Links that loop are already reported while resolving
+            if self.filter_matches(&path) {
+                self.broken_link(&path, &path)?;
+            } else {
+                self.log(Action::SkipFilter(&path));
+            }
+        } else if depth == 0 {
+            // Given explicitly, so nothing happening is worth pointing out
+            self.log(Action::SkipNotLink(&path));
+        } else if path.is_file() {
+            self.log_verbose(Action::SkipFile(&path));
+        }
+
+        Ok(())
+    }
+
+    /// Checks if the directory at `path` is flattened into its parent, which
+    /// is the case for everything below the top of a populated directory
+    /// with [`Options::flatten`].
+    fn flattens(&self, path: &Path) -> bool {
+        self.options.flatten.is_some()
+            && self
+                .staging
+                .as_ref()
+                .is_some_and(|(_, target)| target != path)
+    }
+
+    /// First name for `path` with a counter added that no earlier entry has
+    /// taken, for [`Collision::Rename`].
+    fn free_name(&self, path: &Path) -> PathBuf {
+        let stem = path.file_stem().unwrap_or_default();
+        let mut counter = 1_usize;
+        loop {
+            let mut name = stem.to_owned();
+            name.push(format!("-{counter}"));
+            if let Some(extension) = path.extension() {
+                name.push(".");
+                name.push(extension);
+            }
+            let candidate = path.with_file_name(name);
+            if !self.collides(&candidate) {
+                return candidate;
+            }
+            counter += 1;
+        }
+    }
+
+    /// Checks if populating `path` from `dest` would include a directory that
+    /// `path` is already being populated from.
+    fn populates_itself(&self, path: &Path, dest: &Path) -> io::Result<bool> {
+        // Only a link can lead back up
+        if self.populating.is_empty() || !dest.is_symlink() {
+            return Ok(false);
+        }
+        let dest = canonicalize(dest)?;
+        Ok(path
+            .ancestors()
+            .skip(1)
+            .filter_map(|dir| self.populating.get(dir))
+            .any(|source| source.starts_with(&dest)))
+    }
+
+    /// Checks `path` against the glob filters and [`Options::exclude_from`].
+    fn filter_matches(&self, path: &Path) -> bool {
+        self.options.filter_matches(path)
+            && (self.excluded.is_empty()
+                || link_location(path).is_none_or(|path| !self.excluded.contains(&path)))
+    }
+
+    /// Clears `path` for its replacement.
+    ///
+    /// Original links are recorded in the journal, and renamed with
+    /// [`Options::backup`] instead of being removed if set.
+    fn make_room(&mut self, path: &Path, replacement: Replacement) -> io::Result<()> {
+        if self.staging.is_none()
+            && let Some(journal) = &mut self.journal
+        {
+            journal.record(replacement, path, &read_link(path)?)?;
+        }
+
+        if self.staging.is_none()
+            && let Some(suffix) = &self.options.backup
+        {
+            let backup = backup_path(path, suffix);
+            self.log(Action::Backup {
+                link: path,
+                backup: &backup,
+            });
+            if !self.options.dry_run {
+                self.retrying(|| fs::rename(path, &backup))?;
+            }
+            Ok(())
+        } else if !self.options.dry_run {
+            self.retrying(|| maybe_remove_file(path))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Moves the staged `temp` into the existing `target`, for
+    /// [`Options::merge`].
+    fn merge(&mut self, temp: &Path, target: &Path) -> io::Result<()> {
+        // Skipped with Options::no_clobber
+        if temp.symlink_metadata().is_err() {
+            return Ok(());
+        }
+        let is_dir = |path: &Path| path.symlink_metadata().is_ok_and(|meta| meta.is_dir());
+        if is_dir(temp) && is_dir(target) {
+            for entry in read_dir(temp)? {
+                let name = entry?.file_name();
+                self.merge(&temp.join(&name), &target.join(&name))?;
+            }
+            return fs::remove_dir(temp);
+        }
+
+        if is_dir(target) {
+            return Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("Cannot replace the directory {target:?} with {temp:?}"),
+            ));
+        }
+        if let Some(suffix) = &self.options.backup
+            && target.symlink_metadata().is_ok()
+        {
+            let backup = backup_path(target, suffix);
+            self.log(Action::Backup {
+                link: target,
+                backup: &backup,
+            });
+            self.retrying(|| fs::rename(target, &backup))?;
+        }
+        self.retrying(|| fs::rename(temp, target))
+    }
+
+    /// Copies the file `src` to `dest`, along with any metadata to preserve.
+    fn copy_into(&mut self, src: &Path, dest: &Path) -> io::Result<()> {
+        self.retrying(|| (self.copy)(src, dest))?;
+        if self.options.preserve_timestamps {
+            copy_times(src, dest)?;
+        }
+        if self.options.preserve_xattr {
+            copy_xattrs(src, dest)?;
+        }
+        if self.options.preserve_owner && self.keep_owner(src, dest)? {
+            // Changing the owner can clear setuid and setgid
+            fs::set_permissions(dest, src.metadata()?.permissions())?;
+        }
+        if self.options.preserve_exec {
+            copy_exec_bits(src, dest, self.umask)?;
+        }
+        Ok(())
+    }
+
+    /// Runs `op` until it succeeds, fails in a way that is unlikely to pass,
+    /// or has been retried [`Options::retry`] times.
+    fn retrying<T>(&self, mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+        let mut delay = Duration::from_millis(10);
+        for _ in 0..self.options.retry {
+            match op() {
+                Err(e) if is_transient(&e) => {
+                    thread::sleep(delay);
+                    delay *= 2;
+                }
+                result => return result,
+            }
+        }
+        op()
+    }
+
+    /// Gives `dest` the owner of `src`, when [`Options::preserve_owner`] is
+    /// set.
+    ///
+    /// Warns instead of failing when that is not permitted. Returns whether
+    /// the owner was changed.
+    fn keep_owner(&mut self, src: &Path, dest: &Path) -> io::Result<bool> {
+        if !self.options.preserve_owner {
+            return Ok(false);
+        }
+        match copy_owner(src, dest) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+                self.warn(src, &format!("Cannot keep the owner: {e}"));
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Checks if `dest` was removed since it was resolved, right before it is
+    /// copied or populated from.
+    fn vanished(&self, dest: &Path) -> bool {
+        (self.before_use)(dest);
+        !dest.exists()
+    }
+
+    /// Asks whether to replace the link at `path` with `dest`, when
+    /// [`Options::interactive`] is set.
+    ///
+    /// Only links in the original tree are asked about, since anything in a
+    /// populated directory is new.
+    fn confirm(&mut self, path: &Path, dest: &Path) -> io::Result<bool> {
+        let Some(answers) = &mut self.answers else {
+            return Ok(true);
+        };
+        if self.staging.is_some() {
+            return Ok(true);
+        }
+
+        eprint!("Overwrite {path:?} with {dest:?}? [y/N] ");
+        let mut answer = String::new();
+        answers.read_line(&mut answer)?;
+        Ok(["y", "yes"].contains(&answer.trim().to_lowercase().as_str()))
+    }
+
+    /// Reports the warning `message` about `path` alongside errors.
+    fn warn(&mut self, path: &Path, message: &str) {
+        let _ = write_warning(
+            &mut self.errors,
+            self.options.error_format,
+            path,
+            message,
+            self.color_errors,
+        );
+    }
+
+    /// Decides whether to continue after the failure `e` at `path` with
+    /// [`Options::on_error`], reporting `e` if so.
+    ///
+    /// Returns `e` if the run should stop, with a [`DelinkError`] for the
+    /// caller to report.
+    fn handle_error(&mut self, path: &Path, e: io::Error) -> io::Result<()> {
+        self.summary.errors += 1;
+        let proceed = match self.options.on_error {
+            OnError::Skip => true,
+            OnError::Abort => false,
+            OnError::Prompt => {
+                eprint!("{path:?}: {e}\nContinue after this error? [y/N] ");
+                let mut answer = String::new();
+                if let Some(answers) = &mut self.answers {
+                    answers.read_line(&mut answer)?;
+                }
+                ["y", "yes"].contains(&answer.trim().to_lowercase().as_str())
+            }
+        };
+        if proceed {
+            let _ = write_error(
+                &mut self.errors,
+                self.options.error_format,
+                Some(path),
+                &e,
+                self.color_errors,
+            );
+            Ok(())
+        } else {
+            self.aborted = true;
+            Err(if DelinkError::from_io(&e).is_some() {
+                e
+            } else {
+                DelinkError::Resolve {
+                    path: path.to_path_buf(),
+                    source: e,
+                }
+                .into()
+            })
+        }
+    }
+
+    /// Rewrites the absolute link at `path` to point to `dest` relative to its
+    /// own directory.
+    ///
+    /// Relative links, and links with no relative path to `dest`, are left
+    /// alone.
+    fn relativize(&mut self, path: &Path, dest: &Path) -> io::Result<()> {
+        if read_link(path)?.is_relative() {
+            return Ok(());
+        }
+        let Some(relative) = link_location(path)
+            .as_deref()
+            .and_then(Path::parent)
+            .and_then(|dir| relative_path(dir, dest))
+        else {
+            return Ok(());
+        };
+
+        self.log(Action::Relativize {
+            link: path,
+            target: &relative,
+        });
+        self.replace_link(path, &relative)
+    }
+
+    /// Rewrites the relative link at `path` to point to the canonical `dest`.
+    ///
+    /// Absolute links are left alone.
+    fn absolutize(&mut self, path: &Path, dest: &Path) -> io::Result<()> {
+        if read_link(path)?.is_absolute() {
+            return Ok(());
+        }
+
+        self.log(Action::Absolutize {
+            link: path,
+            target: dest,
+        });
+        self.replace_link(path, dest)
+    }
+
+    /// Replaces the link at `path` with a new link to `target`.
+    fn replace_link(&mut self, path: &Path, target: &Path) -> io::Result<()> {
+        let times = self.link_times(path)?;
+        self.make_room(path, Replacement::Symlink)?;
+        if !self.options.dry_run {
+            self.create_link(target, path, times)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the metadata of the link at `path` to keep its times, when
+    /// [`Options::preserve_link_times`] is set.
+    fn link_times(&self, path: &Path) -> io::Result<Option<fs::Metadata>> {
+        self.options
+            .preserve_link_times
+            .then(|| path.symlink_metadata())
+            .transpose()
+    }
+
+    /// Creates a link at `link` pointing to `target`, with the times from
+    /// [`Self::link_times`] if any.
+    fn create_link(
+        &self,
+        target: &Path,
+        link: &Path,
+        times: Option<fs::Metadata>,
+    ) -> io::Result<()> {
+        symlink(target, link)?;
+        match times {
+            Some(times) => set_link_times(link, &times),
+            None => Ok(()),
+        }
+    }
+
+    /// Handles `path`, originally the broken link at `source`.
+    ///
+    /// Unless pruned, links in populated directories are recreated as is.
+    /// Links outside of them are still rewritten with [`Options::relativize`]
+    /// and [`Options::absolutize`], from what is stored in the link.
+    fn broken_link(&mut self, path: &Path, source: &Path) -> io::Result<()> {
+        if self.options.prune_broken {
+            self.log(Action::PruneBroken(path));
+            if self.staging.is_none() {
+                self.make_room(path, Replacement::Prune)?;
+            }
+        } else if self.options.relativize && self.staging.is_none() {
+            self.relativize(path, &stored_target(path)?)?;
+        } else if self.options.absolutize && self.staging.is_none() {
+            self.absolutize(path, &stored_target(path)?)?;
+        } else {
+            self.log(Action::SkipBroken(path));
+            if self.staging.is_some() && !self.options.dry_run {
+                self.create_link(
+                    &read_link(source)?,
+                    &self.staged(path),
+                    self.link_times(source)?,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks if anything other than a single link among the inputs leads to
+    /// the canonical `dest`.
+    fn shared(&self, dest: &Path) -> bool {
+        self.references.get(dest).is_some_and(|&count| count > 1)
+            || dest
+                .ancestors()
+                .skip(1)
+                .any(|dir| self.references.contains_key(dir))
+    }
+
+    /// Checks if an earlier entry of a populated directory already took the
+    /// place of `path`, as happens when names only differ by case on a
+    /// case-insensitive filesystem.
+    ///
+    /// Anything already in a staging directory was put there by this run.
+    /// Dry runs create nothing to collide with.
+    fn collides(&self, path: &Path) -> bool {
+        self.staging.is_some()
+            && !self.options.dry_run
+            && self.staged(path).symlink_metadata().is_ok()
+    }
+
+    /// Checks if the entries of a directory at `depth` are past the limit.
+    fn too_deep(&self, depth: usize) -> bool {
+        self.options
+            .max_depth
+            .is_some_and(|max_depth| depth >= max_depth)
+    }
+
+    /// Maps `path` to where it is currently being built on disk.
+    fn staged<'p>(&self, path: &'p Path) -> Cow<'p, Path> {
+        if let Some((temp, target)) = &self.staging
+            && let Ok(rest) = path.strip_prefix(target)
+        {
+            if rest.as_os_str().is_empty() {
+                Cow::Owned(temp.clone())
+            } else {
+                Cow::Owned(temp.join(rest))
+            }
+        } else {
+            Cow::Borrowed(path)
+        }
+    }
+}
+
+/// Queues up filling the directory at `path` with the entries of `dest`.
+fn populate(stack: &mut Vec<Work>, path: &Path, dest: &Path, depth: usize) -> io::Result<()> {
+    let mut entries = Vec::new();
+    for sub_dest in read_dir(dest)? {
+        let sub_dest = sub_dest?.path();
+        if let Some(sub_dest_file_name) = sub_dest.file_name() {
+            entries.push(Work::Relink {
+                path: path.join(sub_dest_file_name),
+                symlink_dest: Some(sub_dest),
+                depth: depth + 1,
+            });
+        }
+    }
+
+    // Reversed to pop in directory order
+    stack.extend(entries.into_iter().rev());
+    Ok(())
+}
+
+/// Returns `path` with `suffix` appended, followed by a counter if that
+/// already exists.
+fn backup_path(path: &Path, suffix: &OsStr) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(suffix);
+
+    let mut candidate = PathBuf::from(&backup);
+    let mut counter = 1_usize;
+    while candidate.symlink_metadata().is_ok() {
+        let mut numbered = backup.clone();
+        numbered.push(format!(".{counter}"));
+        candidate = PathBuf::from(numbered);
+        counter += 1;
+    }
+    candidate
+}
+
+/// Returns a hidden sibling of `path` to build its replacement in.
+fn staging_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".delink-{}", process::id()));
+    path.with_file_name(name)
+}
+
+/// Recursively resolves all soft links in `paths` with default [`Options`].
+///
+/// Each action taken is logged to `writer`.
+pub fn resolve_all<W, I>(writer: &mut W, paths: I) -> io::Result<Summary>
+where
+    W: Write,
+    I: IntoIterator<Item = PathBuf>,
+{
+    resolve_all_with(writer, paths, &Options::default())
+}
+
+/// Recursively resolves all soft links in `paths`.
+///
+/// Each action taken is logged to `writer`, followed by a [`Summary`] line
+/// unless [`Options::no_summary`] is set. Nothing is logged if
+/// [`Options::quiet`] is set.
+///
+/// An input that fails is counted in [`Summary::errors`], then handled by
+/// [`Options::on_error`]. Skipped failures are logged to stderr with
+/// [`write_error`]. A failure that stops the run, such as going over
+/// [`Options::max_total_bytes`], is returned for the caller to report
+/// instead, with a [`DelinkError`] for the path that failed.
+/// Errors from steps that act on a known path, such as copying a file, carry
+/// a [`DelinkError`] too.
+///
+/// Input paths are the same with or without trailing separators, so a link
+/// to a directory given as `link/` is still populated in place of the link.
+/// Every path is taken from `paths` before the first is processed, to find
+/// the inputs inside of other inputs.
+pub fn resolve_all_with<W, I>(writer: &mut W, paths: I, options: &Options) -> io::Result<Summary>
+where
+    W: Write,
+    I: IntoIterator<Item = PathBuf>,
+{
+    let (summary, result) = resolve_all_with_summary(writer, paths, options);
+    result.map(|()| summary)
+}
+
+/// Recursively resolves all soft links in `paths`, like [`resolve_all_with`],
+/// also returning the [`Summary`] of a run that fails.
+///
+/// That summary covers everything done before the run stopped, as logged at
+/// the end of the run.
+pub fn resolve_all_with_summary<W, I>(
+    writer: &mut W,
+    paths: I,
+    options: &Options,
+) -> (Summary, io::Result<()>)
+where
+    W: Write,
+    I: IntoIterator<Item = PathBuf>,
+{
+    let mut ctx = match Context::new(writer, options).prepare() {
+        Ok(ctx) => ctx,
+        Err(e) => return (Summary::default(), Err(e)),
+    };
+    let result = ctx.exec(paths);
+    (ctx.summary, result)
+}
+
+/// Recursively resolves all soft links in `paths`, passing each action taken
+/// to `on_action` instead of logging it.
+///
+/// Actions are passed in order on the calling thread, so [`Options::jobs`]
+/// and [`Options::tree_jobs`] are ignored. Paths are passed as is, without
+/// [`Options::relative_to`], and nothing else is logged. See [`text_logger`]
+/// to log the usual [`Format::Text`] lines.
+pub fn resolve_all_with_callback<I, F>(
+    paths: I,
+    options: &Options,
+    mut on_action: F,
+) -> io::Result<Summary>
+where
+    I: IntoIterator<Item = PathBuf>,
+    F: FnMut(Action<'_>),
+{
+    let options = Options {
+        jobs: None,
+        tree_jobs: None,
+        ..options.clone()
+    };
+    let mut sink = io::sink();
+    let mut ctx = Context::new(&mut sink, &options).prepare()?;
+    ctx.on_action = Some(&mut on_action);
+    ctx.exec(paths)?;
+    Ok(ctx.summary)
+}
+
+/// Callback for [`resolve_all_with_callback`] that logs each action to
+/// `writer` as a [`Format::Text`] line.
+pub fn text_logger<W: Write>(writer: &mut W) -> impl FnMut(Action<'_>) + '_ {
+    move |action| {
+        let _ = writeln!(writer, "{action}");
+    }
+}
+
+impl<W: Write> Context<'_, W> {
+    fn exec<I>(&mut self, paths: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = PathBuf>,
+    {
+        let start = Instant::now();
+        if self.options.preserve_exec {
+            // Before any threads start, since reading it can briefly clear it
+            self.umask = umask();
+        }
+        // Read in full before anything is processed, since an input can be
+        // inside of any other input, even a later one. Moves and the
+        // progress total also depend on every input.
+        let paths: Vec<_> = paths
+            .into_iter()
+            .flat_map(|path| {
+                if self.options.glob {
+                    expand_glob(path)
+                } else {
+                    vec![path]
+                }
+            })
+            .map(normalize_input)
+            .collect();
+        if self.options.move_files {
+            self.references = link_references(&paths);
+        }
+        let contained = contained_inputs(&paths);
+        if let Some(progress) = &mut self.progress {
+            let independent: Vec<_> = paths
+                .iter()
+                .zip(&contained)
+                .filter(|(_, contained)| !**contained)
+                .map(|(path, _)| path.clone())
+                .collect();
+            // Contained inputs are only skipped
+            let skipped = paths.len() - independent.len();
+            progress.start(count_actions(&independent) + skipped);
+        }
+        let verified = (self.options.verify && !self.options.dry_run).then(|| {
+            if self.options.into.is_some() {
+                // Only the copies are resolved
+                (paths.iter())
+                    .filter_map(|path| self.mirror_target(&path::absolute(path).ok()?))
+                    .collect()
+            } else {
+                paths.clone()
+            }
+        });
+        let inputs = paths.into_iter().zip(contained);
+        let result = match self.options.jobs {
+            Some(jobs) if jobs.get() > 1 && !self.options.serial() => {
+                let mut independent = Vec::new();
+                for (path, contained) in inputs {
+                    if contained {
+                        self.log(Action::SkipContained(&path));
+                    } else {
+                        independent.push(path);
+                    }
+                }
+                self.exec_parallel(independent, jobs.get())
+            }
+            _ => inputs.into_iter().try_for_each(|(path, contained)| {
+                if contained {
+                    self.log(Action::SkipContained(&path));
+                    Ok(())
+                } else {
+                    self.exec_one(path)
+                }
+            }),
+        };
+        // Everything after the limit is left in place on purpose
+        if let Some(paths) = verified
+            && result.is_ok()
+            && !self.stopped
+        {
+            self.verify(&paths);
+        }
+        let result = match &self.options.manifest {
+            Some(path) if !self.options.dry_run => {
+                // Written even after a failure, for what was replaced
+                let written = manifest::write(path, mem::take(&mut self.manifest));
+                result.and(written)
+            }
+            _ => result,
+        };
+        self.finish_progress();
+        self.summary.elapsed = start.elapsed();
+        self.summary.distinct_targets = self.targets.len();
+
+        if self.options.count_only || !(self.options.no_summary || self.options.quiet) {
+            let _ = match self.options.format {
+                Format::Text => writeln!(self.writer, "{}", self.summary),
+                Format::Json => writeln!(self.writer, "{}", self.summary.json()),
+            };
+        }
+        if self.options.time {
+            let elapsed = self.summary.elapsed;
+            let _ = match self.options.format {
+                Format::Text => writeln!(self.writer, "ELAPSED {elapsed:?}"),
+                Format::Json => writeln!(
+                    self.writer,
+                    r#"{{"action":"elapsed","secs":{}}}"#,
+                    elapsed.as_secs_f64()
+                ),
+            };
+        }
+
+        result
+    }
+
+    /// Processes a single input `path`.
+    ///
+    /// Only returns an error if the run should stop.
+    fn exec_one(&mut self, path: PathBuf) -> io::Result<()> {
+        if self.stopped {
+            return Ok(());
+        }
+        let exists = match &self.target_root {
+            Some(root) if path.is_symlink() => match canonicalize_under(root, &path) {
+                Ok(_) => Ok(true),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+                Err(e) => Err(e),
+            },
+            _ => path.try_exists(),
+        };
+        // Only the stored target of a broken link can be rewritten
+        let convertible = exists.as_ref().is_ok_and(|&x| !x)
+            && path.is_symlink()
+            && (self.options.relativize || self.options.absolutize)
+            && self.options.into.is_none();
+        if exists.as_ref().is_ok_and(|&x| x) || convertible {
+            let result = if self.options.into.is_some() {
+                self.mirror(&path)
+            } else if convertible {
+                absolute_input(&path).and_then(|absolute| self.relink(absolute, None, 0))
+            } else {
+                // Resolved from the same path that is replaced, so a link to a
+                // directory is never taken for a path inside of itself
+                absolute_input(&path).and_then(|absolute| {
+                    let symlink_dest = self.resolve_symlink(&absolute)?;
+                    self.relink(absolute, symlink_dest, 0)
+                })
+            };
+            match result {
+                // Already handled by the entry that failed
+                Err(e) if self.aborted => Err(e),
+                Err(e) => self.handle_error(&path, e),
+                Ok(()) => Ok(()),
+            }
+        } else if exists.is_ok() && path.is_symlink() {
+            // Somewhere along the chain of links is missing, unlike links
+            // that loop
+            self.log(Action::SkipDangling(&path));
+            Ok(())
+        } else {
+            self.log(Action::SkipInput(&path));
+            Ok(())
+        }
+    }
+
+    /// Processes `paths` on `jobs` threads.
+    ///
+    /// Each path is logged into its own buffer, which is written out whole
+    /// once that path finishes, so lines from different paths never mix.
+    fn exec_parallel<I>(&mut self, paths: I, jobs: usize) -> io::Result<()>
+    where
+        I: IntoIterator<Item = PathBuf>,
+    {
+        let queue = Mutex::new(paths.into_iter().collect::<Vec<_>>().into_iter());
+        let (sender, receiver) = mpsc::channel();
+        let (options, copy, references) = (self.options, self.copy, &self.references);
+        let (before_use, umask) = (self.before_use, self.umask);
+
+        thread::scope(|s| {
+            let workers: Vec<_> = (0..jobs)
+                .map(|_| {
+                    let (queue, sender) = (&queue, sender.clone());
+                    s.spawn(move || -> io::Result<()> {
+                        let mut buffer = Vec::new();
+                        let mut ctx = Context::new(&mut buffer, options).prepare()?;
+                        ctx.copy = copy;
+                        ctx.before_use = before_use;
+                        ctx.umask = umask;
+                        ctx.references = references.clone();
+                        // Reported from the calling thread instead
+                        ctx.progress = None;
+
+                        while let Some(path) = queue.lock().unwrap().next() {
+                            let result = ctx.exec_one(path.clone());
+                            let output = (
+                                path,
+                                mem::take(ctx.writer),
+                                mem::take(&mut ctx.summary),
+                                mem::take(&mut ctx.kept),
+                                mem::take(&mut ctx.processed),
+                                mem::take(&mut ctx.manifest),
+                                mem::take(&mut ctx.targets),
+                            );
+                            if sender.send(output).is_err() {
+                                break;
+                            }
+                            if let Err(e) = result {
+                                // Stops the other jobs after their current path
+                                *queue.lock().unwrap() = Vec::new().into_iter();
+                                return Err(e);
+                            }
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            drop(sender);
+
+            for (path, output, summary, kept, processed, manifest, targets) in receiver {
+                self.summary.merge(summary);
+                self.kept.extend(kept);
+                self.processed += processed;
+                self.manifest.extend(manifest);
+                self.targets.extend(targets);
+                if let Some(progress) = &mut self.progress {
+                    progress.update(&self.summary, self.processed, &path);
+                }
+                let _ = self.writer.write_all(&output);
+            }
+            workers
+                .into_iter()
+                .try_for_each(|worker| worker.join().unwrap())
+        })
+    }
+}
+
+/// Every path matching the glob `pattern`, or just `pattern` if there are
+/// none.
+fn expand_glob(pattern: PathBuf) -> Vec<PathBuf> {
+    let options = glob::MatchOptions {
+        require_literal_leading_dot: true,
+        ..glob::MatchOptions::new()
+    };
+    let matches: Vec<_> = pattern
+        .to_str()
+        .and_then(|pattern| glob::glob_with(pattern, options).ok())
+        .into_iter()
+        .flatten()
+        // Unreadable directories are left out
+        .flatten()
+        .collect();
+    if matches.is_empty() {
+        vec![pattern]
+    } else {
+        matches
+    }
+}
+
+/// Removes any trailing separators, repeated separators, and `.` components
+/// after the first from the input `path`.
+///
+/// A trailing separator or `.` follows a link to a directory, which would
+/// otherwise walk the linked directory in place instead of populating the
+/// link.
+fn normalize_input(path: PathBuf) -> PathBuf {
+    let trimmed: PathBuf = path.components().collect();
+    if trimmed.as_os_str().is_empty() {
+        path
+    } else {
+        trimmed
+    }
+}
+
+/// Counts the links to each canonical destination in or under `paths`.
+///
+/// Links to directories are counted, but not followed. Anything unreadable
+/// is left out.
+fn link_references(paths: &[PathBuf]) -> HashMap<PathBuf, usize> {
+    let mut references = HashMap::new();
+    walk_links(paths, |link| {
+        if let Ok(dest) = canonicalize(link) {
+            *references.entry(dest).or_default() += 1;
+        }
+    });
+    references
+}
+
+/// Whether `e` may not happen again, such as on a busy network filesystem.
+fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::TimedOut
+            | ErrorKind::ResourceBusy
+    )
+}
+
+/// Estimates how many actions resolving `paths` takes, for
+/// [`Options::progress`].
+///
+/// Each link counts once, along with everything a link to a directory would
+/// be populated with, and each missing input counts once.
+fn count_actions(paths: &[PathBuf]) -> usize {
+    let mut total = paths
+        .iter()
+        .filter(|path| path.symlink_metadata().is_err())
+        .count();
+    walk_links(paths, |link| {
+        total += 1;
+        if let Ok(dest) = canonicalize(link)
+            && dest.is_dir()
+        {
+            total += count_populated(&dest);
+        }
+    });
+    total
+}
+
+/// Counts everything under `dir`, following links to each directory once.
+fn count_populated(dir: &Path) -> usize {
+    let mut count = 0;
+    let mut visited = HashSet::from([dir.to_path_buf()]);
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        let Ok(entries) = read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            count += 1;
+            if let Ok(dest) = canonicalize(entry.path())
+                && dest.is_dir()
+                && visited.insert(dest.clone())
+            {
+                stack.push(dest);
+            }
+        }
+    }
+    count
+}
+
+/// Calls `f` with every link in or under `paths`, without following them.
+///
+/// Anything unreadable is left out.
+fn walk_links(paths: &[PathBuf], mut f: impl FnMut(&Path)) {
+    let mut stack = paths.to_vec();
+    while let Some(path) = stack.pop() {
+        if path.is_symlink() {
+            f(&path);
+        } else if let Ok(entries) = read_dir(&path) {
+            stack.extend(entries.flatten().map(|entry| entry.path()));
+        }
+    }
+}
+
+/// Marks each of `paths` that is inside of, or a repeat of, another input.
+///
+/// Those would otherwise be processed again along with the other input.
+fn contained_inputs(paths: &[PathBuf]) -> Vec<bool> {
+    let locations: Vec<_> = paths.iter().map(|path| link_location(path)).collect();
+    let mut order: Vec<_> = (0..paths.len())
+        .filter(|&idx| locations[idx].is_some())
+        .collect();
+    // Sorted paths keep each directory directly before its contents, and
+    // the stable sort keeps the first of any repeats.
+    order.sort_by(|&a, &b| locations[a].cmp(&locations[b]));
+
+    let mut contained = vec![false; paths.len()];
+    let mut root: Option<&PathBuf> = None;
+    for idx in order {
+        let location = locations[idx]
+            .as_ref()
+            .expect("Filtered to known locations");
+        if root.is_some_and(|root| location.starts_with(root)) {
+            contained[idx] = true;
+        } else {
+            root = Some(location);
+        }
+    }
+    contained
+}
+
+/// Recreates every link recorded in the `journal` file, most recent first.
+///
+/// Anything that replaced a link is removed. Each restored link is logged to
+/// `writer`.
+pub fn undo<W: Write>(writer: &mut W, journal: &Path, options: &Options) -> io::Result<()> {
+    let mut ctx = Context::new(writer, options);
+
+    for entry in journal::read(journal)?.into_iter().rev() {
+        ctx.log(Action::Restore {
+            target: &entry.target,
+            link: &entry.link,
+        });
+        if options.dry_run {
+            continue;
+        }
+
+        match entry.replacement {
+            Replacement::Copy
+            | Replacement::Hardlink
+            | Replacement::Prune
+            | Replacement::Symlink => maybe_remove_file(&entry.link)?,
+            Replacement::Populate => match fs::remove_dir_all(&entry.link) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => (),
+            },
+            // Put the file back where the link pointed
+            Replacement::Move => {
+                let target = match entry.link.parent() {
+                    Some(parent) => parent.join(&entry.target),
+                    None => entry.target.clone(),
+                };
+                fs::rename(&entry.link, target)?
+            }
+        }
+        symlink(&entry.target, &entry.link)?;
+    }
+
+    ctx.finish_progress();
+    Ok(())
+}
+
+/// Splits the paths in `reader` on each `delimiter` byte.
+///
+/// The last path does not need to end in a delimiter. A trailing carriage
+/// return is also removed from newline delimited paths.
+pub fn read_paths<R: BufRead>(
+    reader: R,
+    delimiter: u8,
+) -> impl Iterator<Item = io::Result<PathBuf>> {
+    reader.split(delimiter).map(move |entry| {
+        let mut entry = entry?;
+        if delimiter == b'\n' && entry.last() == Some(&b'\r') {
+            entry.pop();
+        }
+        bytes_to_path(entry)
+    })
+}
+
+/// Replaces the first `-` in `args` with the paths in `reader`, split like
+/// [`read_paths`], keeping everything in order.
+///
+/// Any other `-` is left out, since the reader is already used up.
+pub fn input_paths<I, R>(
+    args: I,
+    reader: R,
+    delimiter: u8,
+) -> impl Iterator<Item = io::Result<PathBuf>>
+where
+    I: IntoIterator,
+    I::Item: Into<PathBuf>,
+    R: BufRead,
+{
+    let mut reader = Some(reader);
+    args.into_iter().map(Into::into).flat_map(move |arg| {
+        let is_stdin = arg.as_os_str() == "-";
+        let stdin = is_stdin.then(|| reader.take()).flatten();
+        (!is_stdin).then_some(Ok(arg)).into_iter().chain(
+            stdin
+                .into_iter()
+                .flat_map(move |reader| read_paths(reader, delimiter)),
+        )
+    })
+}
+
+/// Replaces each `@<FILE>` in `args` with the newline delimited paths in
+/// `FILE`, split like [`read_paths`], keeping everything in order.
+///
+/// A path starting with `@` can still be given another way, like `./@name`.
+pub fn expand_response_files<I>(args: I) -> io::Result<Vec<PathBuf>>
+where
+    I: IntoIterator,
+    I::Item: Into<PathBuf>,
+{
+    let mut paths = Vec::new();
+    for arg in args {
+        let arg = arg.into();
+        match arg.to_str().and_then(|arg| arg.strip_prefix('@')) {
+            Some(file) if !file.is_empty() => {
+                let reader = File::open(file).map_err(|source| DelinkError::ReadPaths {
+                    path: file.into(),
+                    source,
+                })?;
+                for path in read_paths(BufReader::new(reader), b'\n') {
+                    paths.push(path.map_err(|source| DelinkError::ReadPaths {
+                        path: file.into(),
+                        source,
+                    })?);
+                }
+            }
+            _ => paths.push(arg),
+        }
+    }
+    Ok(paths)
+}
+
+/// Converts `arg` to a path if it is a `file://` URL, such as
+/// `file:///home/me/my%20link`, and returns anything else as is.
+///
+/// Percent-encoded bytes are decoded. The only host allowed is `localhost`,
+/// and URLs with any other scheme are rejected.
+pub fn path_from_url(arg: PathBuf) -> io::Result<PathBuf> {
+    let invalid = |reason: &str| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Cannot use {arg:?} as a path: {reason}"),
+        )
+    };
+
+    let Some((scheme, rest)) = arg.to_str().and_then(|arg| arg.split_once("://")) else {
+        return Ok(arg);
+    };
+    // Anything before :// that cannot be a scheme is part of a path
+    let mut chars = scheme.chars();
+    if !chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        || !chars.all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+    {
+        return Ok(arg);
+    }
+    if !scheme.eq_ignore_ascii_case("file") {
+        return Err(invalid("only file:// URLs are supported"));
+    }
+
+    let path = match rest.find('/') {
+        Some(start) if matches!(&rest[..start], "" | "localhost") => &rest[start..],
+        _ => return Err(invalid("only local file:// URLs are supported")),
+    };
+    // Drives come after the root, like file:///C:/
+    #[cfg(windows)]
+    let path = match path.as_bytes() {
+        [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => &path[1..],
+        _ => path,
+    };
+
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut input = path.bytes();
+    while let Some(b) = input.next() {
+        if b == b'%' {
+            let hex = [input.next(), input.next()];
+            let decoded = match hex {
+                [Some(high), Some(low)] => str::from_utf8(&[high, low])
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+                _ => None,
+            };
+            bytes.push(decoded.ok_or_else(|| invalid("invalid percent encoding"))?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    bytes_to_path(bytes)
+}
+
+#[cfg(unix)]
+fn bytes_to_path(bytes: Vec<u8>) -> io::Result<PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+    Ok(OsString::from_vec(bytes).into())
+}
+
+#[cfg(windows)]
+fn bytes_to_path(bytes: Vec<u8>) -> io::Result<PathBuf> {
+    String::from_utf8(bytes)
+        .map(PathBuf::from)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use std::{
+        cell::Cell,
+        fs::{create_dir, create_dir_all, read_dir, read_link},
+        rc::Rc,
+        thread,
+        time::{Duration, SystemTime},
+    };
+
+    use super::*;
+    use crate::temp::Temp;
+
+    /// Copies like [`copy_file`], except that files named `fail` never copy.
+    fn failing_copy(src: &Path, dest: &Path) -> io::Result<u64> {
+        if src.file_name() == Some("fail".as_ref()) {
+            Err(io::Error::other("injected failure"))
+        } else {
+            copy_file(src, dest)
+        }
+    }
+
+    /// Creates a file in `dir` that [`failing_copy`] fails on, and links to it
+    /// and to one that copies, returned as the file and both links.
+    fn failing_links(dir: &Path) -> (PathBuf, PathBuf, PathBuf) {
+        let bad_path = dir.join("fail");
+        let good_path = dir.join("good");
+        let bad_link = dir.join("bad_link");
+        let good_link = dir.join("good_link");
+        File::create(&bad_path).unwrap();
+        File::create(&good_path).unwrap();
+        let _symlink = symlink(&bad_path, &bad_link);
+        let _symlink = symlink(&good_path, &good_link);
+        (bad_path, bad_link, good_link)
+    }
+
+    #[test]
+    fn basic_resolve() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        let _linked_file = File::create(&linked_path).unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+
+        let expected = format!(
+            "SKIP NOT A LINK {linked_path:?}\nCOPY {linked_path:?} => {symlink_path:?}\nSUMMARY copies=1 hardlinks=0 moves=0 populated=0 skipped=1 errors=0 bytes=0 distinct_targets=1\n"
+        );
+        let mut buffer = Vec::new();
+        resolve_all(&mut buffer, [linked_path, symlink_path.clone()]).unwrap();
+        assert_eq!(str::from_utf8(&buffer).unwrap(), expected);
+        assert!(symlink_path.is_file());
+    }
+
+    #[test]
+    fn non_existent() {
+        let dir = Temp::new_dir().unwrap();
+
+        let symlink_path = dir.join("symlink");
+
+        let expected = format!(
+            "SKIP INPUT {symlink_path:?}\nSUMMARY copies=0 hardlinks=0 moves=0 populated=0 skipped=1 errors=0 bytes=0 distinct_targets=0\n"
+        );
+        let mut buffer = Vec::new();
+        resolve_all(&mut buffer, [symlink_path.clone()]).unwrap();
+        assert_eq!(str::from_utf8(&buffer).unwrap(), expected);
+        assert!(!symlink_path.exists());
+    }
+
+    #[test]
+    fn link_to_self() {
+        let dir = Temp::new_dir().unwrap();
+
+        let symlink_path = dir.join("symlink");
+        let _symlink = symlink(&symlink_path, &symlink_path);
+
+        let expected = format!(
+            "SKIP SELF REFERENCE {symlink_path:?}\nSUMMARY copies=0 hardlinks=0 moves=0 populated=0 skipped=1 errors=0 bytes=0 distinct_targets=0\n"
+        );
+        let mut buffer = Vec::new();
+        resolve_all(&mut buffer, [dir.to_path_buf()]).unwrap();
+        assert_eq!(str::from_utf8(&buffer).unwrap(), expected);
+        assert!(symlink_path.is_symlink());
+    }
+
+    #[test]
+    fn dir_link() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let symlink_path = dir.join("symlink");
+        create_dir(&subdir).unwrap();
+
+        let linked_path = subdir.join("linked_file");
+        let _linked_file = File::create(&linked_path).unwrap();
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        let expected = format!(
+            "POPULATE {subdir:?} => {symlink_path:?}\nCOPY {linked_path:?} => {:?}\nSUMMARY copies=1 hardlinks=0 moves=0 populated=1 skipped=0 errors=0 bytes=0 distinct_targets=2\n",
+            symlink_path.join(linked_path.file_name().unwrap())
+        );
+        let mut buffer = Vec::new();
+        resolve_all(&mut buffer, [symlink_path.clone()]).unwrap();
+        assert_eq!(str::from_utf8(&buffer).unwrap(), expected);
+        assert!(symlink_path.is_dir())
+    }
+
+    #[test]
+    fn dir_link_through_parent() {
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.canonicalize().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let symlink_path = dir.join("symlink");
+        create_dir(&subdir).unwrap();
+
+        let linked_path = subdir.join("linked_file");
+        let _linked_file = File::create(&linked_path).unwrap();
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        // Starts inside of the directory the link points to
+        let input = subdir.join("..").join("symlink");
+        let expected = format!(
+            "POPULATE {subdir:?} => {symlink_path:?}\nCOPY {linked_path:?} => {:?}\n",
+            symlink_path.join("linked_file")
+        );
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [input],
+            &Options {
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(str::from_utf8(&buffer).unwrap(), expected);
+        assert!(!symlink_path.is_symlink());
+        assert!(symlink_path.join("linked_file").is_file());
+        assert!(linked_path.is_file());
+    }
+
+    #[test]
+    fn nested_dir_link() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let sub_subdir = subdir.join("inner");
+        let symlink_path = dir.join("symlink");
+        create_dir(&subdir).unwrap();
+        create_dir(&sub_subdir).unwrap();
+
+        let linked_path = sub_subdir.join("linked_file");
+        let _linked_file = File::create(&linked_path).unwrap();
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        let expected = format!(
+            "POPULATE {subdir:?} => {symlink_path:?}\nPOPULATE {sub_subdir:?} => {:?}\nCOPY {linked_path:?} => {:?}\nSUMMARY copies=1 hardlinks=0 moves=0 populated=2 skipped=0 errors=0 bytes=0 distinct_targets=3\n",
+            symlink_path.join(sub_subdir.file_name().unwrap()),
+            symlink_path
+                .join(sub_subdir.file_name().unwrap())
+                .join(linked_path.file_name().unwrap())
+        );
+        let mut buffer = Vec::new();
+        resolve_all(&mut buffer, [symlink_path.clone()]).unwrap();
+        assert_eq!(str::from_utf8(&buffer).unwrap(), expected);
+        assert!(symlink_path.is_dir());
+        assert!(symlink_path.join("inner").is_dir())
+    }
+
+    #[test]
+    fn recursive_dir_link() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let symlink_path = subdir.join("symlink");
+        create_dir(&subdir).unwrap();
+
+        let linked_path = subdir.join("linked_file");
+        let _linked_file = File::create(&linked_path).unwrap();
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        let expected = format!(
+            "SKIP RECURSIVE {symlink_path:?}\nSUMMARY copies=0 hardlinks=0 moves=0 populated=0 skipped=1 errors=0 bytes=0 distinct_targets=0\n"
+        );
+        let mut buffer = Vec::new();
+        resolve_all(&mut buffer, [symlink_path.clone()]).unwrap();
+        assert_eq!(str::from_utf8(&buffer).unwrap(), expected);
+        assert!(symlink_path.is_symlink())
+    }
+
+    #[test]
+    fn dry_run() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let sub_subdir = subdir.join("inner");
+        let dir_symlink_path = dir.join("dir_symlink");
+        let file_symlink_path = dir.join("file_symlink");
+        create_dir(&subdir).unwrap();
+        create_dir(&sub_subdir).unwrap();
+
+        let linked_path = sub_subdir.join("linked_file");
+        let _linked_file = File::create(&linked_path).unwrap();
+        let _symlink = symlink(&subdir, &dir_symlink_path);
+        let _symlink = symlink(&linked_path, &file_symlink_path);
+
+        let inputs = [dir_symlink_path.clone(), file_symlink_path.clone()];
+
+        let mut dry_buffer = Vec::new();
+        resolve_all_with(
+            &mut dry_buffer,
+            inputs.clone(),
+            &Options {
+                dry_run: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert!(dir_symlink_path.is_symlink());
+        assert!(file_symlink_path.is_symlink());
+        assert_eq!(
+            read_dir(&dir).unwrap().count(),
+            3,
+            "No entries should be added"
+        );
+
+        let mut buffer = Vec::new();
+        resolve_all(&mut buffer, inputs).unwrap();
+        assert!(!dir_symlink_path.is_symlink());
+        assert!(!file_symlink_path.is_symlink());
+        assert_eq!(
+            str::from_utf8(&dry_buffer).unwrap(),
+            str::from_utf8(&buffer).unwrap()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn preserve_permissions() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        let _linked_file = File::create(&linked_path).unwrap();
+        fs::set_permissions(&linked_path, fs::Permissions::from_mode(0o4755)).unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+
+        let mut buffer = Vec::new();
+        resolve_all(&mut buffer, [symlink_path.clone()]).unwrap();
+        assert!(symlink_path.is_file());
+        assert_eq!(
+            symlink_path
+                .symlink_metadata()
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o7777,
+            0o4755
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hardlink_same_fs() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        let _linked_file = File::create(&linked_path).unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+
+        let expected = format!(
+            "HARDLINK {linked_path:?} => {symlink_path:?}\nSUMMARY copies=0 hardlinks=1 moves=0 populated=0 skipped=0 errors=0 bytes=0 distinct_targets=1\n"
+        );
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone()],
+            &Options {
+                hardlink: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(str::from_utf8(&buffer).unwrap(), expected);
+        assert!(symlink_path.is_file());
+        assert_eq!(
+            symlink_path.symlink_metadata().unwrap().ino(),
+            linked_path.metadata().unwrap().ino()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hardlink_cross_fs() {
+        let dir = Temp::new_dir().unwrap();
+        // Shared memory is typically a separate tmpfs mount
+        let Ok(other_dir) = Temp::new_dir_in("/dev/shm") else {
+            return;
+        };
+        if dir.metadata().unwrap().dev() == other_dir.metadata().unwrap().dev() {
+            return;
+        }
+
+        let linked_path = other_dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        let _linked_file = File::create(&linked_path).unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+
+        let expected = format!(
+            "COPY {linked_path:?} => {symlink_path:?}\nSUMMARY copies=1 hardlinks=0 moves=0 populated=0 skipped=0 errors=0 bytes=0 distinct_targets=1\n"
+        );
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone()],
+            &Options {
+                hardlink: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(str::from_utf8(&buffer).unwrap(), expected);
+        assert!(symlink_path.is_file());
+        assert_ne!(
+            symlink_path.symlink_metadata().unwrap().dev(),
+            linked_path.metadata().unwrap().dev()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn same_filesystem_populate() {
+        let dir = Temp::new_dir().unwrap();
+        // Shared memory is typically a separate tmpfs mount
+        let Ok(other_dir) = Temp::new_dir_in("/dev/shm") else {
+            return;
+        };
+        if dir.metadata().unwrap().dev() == other_dir.metadata().unwrap().dev() {
+            return;
+        }
+
+        let local_dir = dir.join("local_dir");
+        let local_link = dir.join("local_link");
+        let other_link = dir.join("other_link");
+        create_dir(&local_dir).unwrap();
+        let _symlink = symlink(&local_dir, &local_link);
+        let _symlink = symlink(&other_dir, &other_link);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [local_link.clone(), other_link.clone()],
+            &Options {
+                no_summary: true,
+                same_filesystem: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "POPULATE {local_dir:?} => {local_link:?}\nSKIP XDEV {:?}\n",
+                canonicalize(&other_dir).unwrap()
+            )
+        );
+        assert!(!local_link.is_symlink());
+        assert!(other_link.is_symlink());
+    }
+
+    #[test]
+    fn summary_tallies() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let sub_subdir = subdir.join("inner");
+        let symlink_path = dir.join("symlink");
+        let missing_path = dir.join("missing");
+        create_dir(&subdir).unwrap();
+        create_dir(&sub_subdir).unwrap();
+
+        let linked_path = sub_subdir.join("linked_file");
+        let _linked_file = File::create(&linked_path).unwrap();
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        let mut buffer = Vec::new();
+        let summary = resolve_all_with(
+            &mut buffer,
+            [symlink_path, missing_path],
+            &Options {
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            summary,
+            Summary {
+                copies: 1,
+                hardlinks: 0,
+                moves: 0,
+                populated: 2,
+                skipped: 1,
+                errors: 0,
+                bytes: 0,
+                distinct_targets: 3,
+                elapsed: summary.elapsed,
+            }
+        );
+        assert!(
+            !str::from_utf8(&buffer).unwrap().contains("SUMMARY"),
+            "Summary line should be suppressed"
+        );
+    }
+
+    #[test]
+    fn quiet() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        let self_symlink_path = dir.join("self_symlink");
+        let _linked_file = File::create(&linked_path).unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+        let _symlink = symlink(&self_symlink_path, &self_symlink_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [dir.to_path_buf()],
+            &Options {
+                quiet: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert!(buffer.is_empty());
+        assert!(symlink_path.is_file());
+        assert!(self_symlink_path.is_symlink());
+    }
+
+    #[test]
+    fn json_format() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let sub_subdir = subdir.join("inner");
+        let symlink_path = dir.join("symlink");
+        create_dir(&subdir).unwrap();
+        create_dir(&sub_subdir).unwrap();
+
+        let linked_path = sub_subdir.join("linked_file");
+        let _linked_file = File::create(&linked_path).unwrap();
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone()],
+            &Options {
+                format: Format::Json,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+
+        let lines: Vec<serde_json::Value> = str::from_utf8(&buffer)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let inner_path = symlink_path.join("inner");
+        assert_eq!(
+            lines,
+            [
+                serde_json::json!({
+                    "action": "populate",
+                    "dest": subdir,
+                    "link": symlink_path,
+                }),
+                serde_json::json!({
+                    "action": "populate",
+                    "dest": sub_subdir,
+                    "link": inner_path,
+                }),
+                serde_json::json!({
+                    "action": "copy",
+                    "dest": linked_path,
+                    "link": inner_path.join("linked_file"),
+                }),
+                serde_json::json!({
+                    "action": "summary",
+                    "copies": 1,
+                    "hardlinks": 0,
+                    "moves": 0,
+                    "populated": 2,
+                    "skipped": 0,
+                    "errors": 0,
+                    "bytes": 0,
+                    "distinct_targets": 3,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn json_escaping() {
+        let path = Path::new("quote\"back\\slash\nline\u{1}");
+        let value: serde_json::Value = serde_json::from_str(&JsonStr(path).to_string()).unwrap();
+        assert_eq!(value, path.to_str().unwrap());
+    }
+
+    #[test]
+    fn populate_failure_keeps_link() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let symlink_path = dir.join("symlink");
+        create_dir(&subdir).unwrap();
+        for name in ["a", "b", "fail", "c", "d"] {
+            File::create(subdir.join(name)).unwrap();
+        }
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        let mut buffer = Vec::new();
+        let options = Options::default();
+        let mut ctx = Context::new(&mut buffer, &options);
+        ctx.copy = failing_copy;
+        ctx.exec([symlink_path.clone()]).unwrap();
+        assert_eq!(ctx.summary.errors, 1);
+
+        assert!(symlink_path.is_symlink());
+        assert_eq!(read_link(&symlink_path).unwrap(), subdir);
+        assert_eq!(
+            read_dir(&dir).unwrap().count(),
+            2,
+            "Staging directory should be cleaned up"
+        );
+    }
+
+    #[test]
+    fn vanished_target() {
+        /// Removes anything named `gone` once resolved, as another process
+        /// could.
+        fn remove_gone(dest: &Path) {
+            if dest.file_name() == Some("gone".as_ref()) {
+                remove_file(dest).unwrap();
+            }
+        }
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.canonicalize().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let file_link = dir.join("file_link");
+        let dir_link = dir.join("dir_link");
+        create_dir(&subdir).unwrap();
+        File::create(dir.join("gone")).unwrap();
+        File::create(subdir.join("gone")).unwrap();
+        let _symlink = symlink(dir.join("gone"), &file_link);
+        let _symlink = symlink(&subdir, &dir_link);
+
+        let mut buffer = Vec::new();
+        let options = Options {
+            no_summary: true,
+            ..Options::default()
+        };
+        let mut ctx = Context::new(&mut buffer, &options);
+        ctx.before_use = remove_gone;
+        ctx.exec([file_link.clone(), dir_link.clone()]).unwrap();
+        assert_eq!(ctx.summary.skipped, 2);
+        assert_eq!(ctx.summary.errors, 0);
+
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "SKIP VANISHED {:?}\nPOPULATE {subdir:?} => {dir_link:?}\nSKIP VANISHED {:?}\n",
+                dir.join("gone"),
+                subdir.join("gone")
+            )
+        );
+        assert!(file_link.is_symlink());
+        assert!(!dir_link.is_symlink());
+        assert_eq!(read_dir(&dir_link).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn two_link_cycle() {
+        let dir = Temp::new_dir().unwrap();
+
+        let first_path = dir.join("first");
+        let second_path = dir.join("second");
+        let _symlink = symlink(&second_path, &first_path);
+        let _symlink = symlink(&first_path, &second_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [dir.to_path_buf()],
+            &Options {
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        let mut lines: Vec<_> = str::from_utf8(&buffer).unwrap().lines().collect();
+        lines.sort();
+        assert_eq!(
+            lines,
+            [
+                format!("SKIP CYCLE {first_path:?}"),
+                format!("SKIP CYCLE {second_path:?}")
+            ]
+        );
+        assert!(first_path.is_symlink());
+        assert!(second_path.is_symlink());
+    }
+
+    #[test]
+    fn three_link_cycle() {
+        let dir = Temp::new_dir().unwrap();
+
+        let first_path = dir.join("first");
+        let second_path = dir.join("second");
+        let third_path = dir.join("third");
+        let _symlink = symlink(&second_path, &first_path);
+        // Relative links are part of the cycle as well
+        let _symlink = symlink("third", &second_path);
+        let _symlink = symlink(&first_path, &third_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [dir.to_path_buf()],
+            &Options {
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        let mut lines: Vec<_> = str::from_utf8(&buffer).unwrap().lines().collect();
+        lines.sort();
+        assert_eq!(
+            lines,
+            [
+                format!("SKIP CYCLE {first_path:?}"),
+                format!("SKIP CYCLE {second_path:?}"),
+                format!("SKIP CYCLE {third_path:?}")
+            ]
+        );
+    }
+
+    #[test]
+    fn max_depth() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let nested_dir = subdir.join("a").join("b").join("c");
+        let symlink_path = dir.join("symlink");
+        create_dir_all(&nested_dir).unwrap();
+        File::create(nested_dir.join("linked_file")).unwrap();
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        let expected = format!(
+            "POPULATE {subdir:?} => {symlink_path:?}\nPOPULATE {:?} => {:?}\nSKIP DEPTH {:?}\n",
+            subdir.join("a"),
+            symlink_path.join("a"),
+            symlink_path.join("a").join("b"),
+        );
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone()],
+            &Options {
+                no_summary: true,
+                max_depth: Some(2),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(str::from_utf8(&buffer).unwrap(), expected);
+        assert!(symlink_path.join("a").is_dir());
+        assert!(symlink_path.join("a").join("b").is_symlink());
+        assert!(
+            symlink_path
+                .join("a")
+                .join("b")
+                .join("c")
+                .join("linked_file")
+                .is_file()
+        );
+    }
+
+    #[test]
+    fn max_depth_zero() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        let _linked_file = File::create(&linked_path).unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+
+        let expected = format!("SKIP DEPTH {:?}\n", dir.to_path_buf());
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [dir.to_path_buf()],
+            &Options {
+                no_summary: true,
+                max_depth: Some(0),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(str::from_utf8(&buffer).unwrap(), expected);
+        assert!(symlink_path.is_symlink());
+    }
+
+    #[test]
+    fn deep_nesting() {
+        // Deep enough to overflow the small stack below when recursing, while
+        // staying under PATH_MAX.
+        const DEPTH: usize = 1_500;
+
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let symlink_path = dir.join("symlink");
+        let mut nested_dir = subdir.clone();
+        for _ in 0..DEPTH {
+            nested_dir.push("d");
+        }
+        create_dir_all(&nested_dir).unwrap();
+        File::create(nested_dir.join("f")).unwrap();
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        let inner_symlink_path = symlink_path.clone();
+        let summary = thread::Builder::new()
+            .stack_size(64 * 1024)
+            .spawn(move || resolve_all(&mut io::sink(), [inner_symlink_path]).unwrap())
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(summary.populated, DEPTH + 1);
+        assert_eq!(summary.copies, 1);
+
+        let mut nested_link = symlink_path.clone();
+        for _ in 0..DEPTH {
+            nested_link.push("d");
+        }
+        assert!(nested_link.join("f").is_file());
+    }
+
+    #[test]
+    fn backup_default_suffix() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        let backup_path = dir.join("symlink.bak");
+        let _linked_file = File::create(&linked_path).unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+
+        let expected = format!(
+            "COPY {linked_path:?} => {symlink_path:?}\nBACKUP {symlink_path:?} => {backup_path:?}\n"
+        );
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone()],
+            &Options {
+                no_summary: true,
+                backup: Some(".bak".into()),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(str::from_utf8(&buffer).unwrap(), expected);
+        assert!(symlink_path.is_file());
+        assert_eq!(read_link(&backup_path).unwrap(), linked_path);
+    }
+
+    #[test]
+    fn backup_collision() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let symlink_path = dir.join("symlink");
+        let backup_path = dir.join("symlink.bak.1");
+        create_dir(&subdir).unwrap();
+        File::create(subdir.join("linked_file")).unwrap();
+        File::create(dir.join("symlink.bak")).unwrap();
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone()],
+            &Options {
+                no_summary: true,
+                backup: Some(".bak".into()),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert!(
+            str::from_utf8(&buffer)
+                .unwrap()
+                .ends_with(&format!("BACKUP {symlink_path:?} => {backup_path:?}\n"))
+        );
+        assert!(symlink_path.join("linked_file").is_file());
+        assert!(dir.join("symlink.bak").is_file());
+        assert_eq!(read_link(&backup_path).unwrap(), subdir);
+    }
+
+    #[test]
+    fn journal_undo() {
+        let dir = Temp::new_dir().unwrap();
+        let journal_path = Temp::new_file().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let linked_path = subdir.join("linked_file");
+        let dir_symlink_path = dir.join("dir_symlink");
+        let file_symlink_path = dir.join("file_symlink");
+        create_dir(&subdir).unwrap();
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink("real_dir", &dir_symlink_path);
+        let _symlink = symlink(&linked_path, &file_symlink_path);
+
+        let options = Options {
+            no_summary: true,
+            journal: Some(journal_path.to_path_buf()),
+            ..Options::default()
+        };
+        resolve_all_with(&mut io::sink(), [dir.to_path_buf()], &options).unwrap();
+        assert!(dir_symlink_path.is_dir() && !dir_symlink_path.is_symlink());
+        assert!(file_symlink_path.is_file() && !file_symlink_path.is_symlink());
+
+        let mut buffer = Vec::new();
+        undo(&mut buffer, &journal_path, &options).unwrap();
+        assert_eq!(str::from_utf8(&buffer).unwrap().lines().count(), 2);
+        assert_eq!(read_link(&dir_symlink_path).unwrap(), Path::new("real_dir"));
+        assert_eq!(read_link(&file_symlink_path).unwrap(), linked_path);
+        assert!(dir_symlink_path.join("linked_file").is_file());
+    }
+
+    #[test]
+    fn preserve_timestamps() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        let mtime = SystemTime::now() - Duration::from_secs(60 * 60 * 24);
+        File::create(&linked_path)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+
+        resolve_all_with(
+            &mut io::sink(),
+            [symlink_path.clone()],
+            &Options {
+                preserve_timestamps: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        let copied_mtime = symlink_path.symlink_metadata().unwrap().modified().unwrap();
+        let difference = copied_mtime
+            .duration_since(mtime)
+            .unwrap_or_else(|e| e.duration());
+        assert!(difference < Duration::from_secs(1), "{difference:?}");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn preserve_xattr() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        File::create(&linked_path).unwrap();
+        xattr::set(&linked_path, "user.test", b"value").unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+
+        resolve_all_with(
+            &mut io::sink(),
+            [symlink_path.clone()],
+            &Options {
+                preserve_xattr: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert!(!symlink_path.is_symlink());
+        assert_eq!(
+            xattr::get(&symlink_path, "user.test").unwrap().as_deref(),
+            Some(&b"value"[..])
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn preserve_dir_perms() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let symlink_path = dir.join("symlink");
+        create_dir(&subdir).unwrap();
+        create_dir(subdir.join("nested")).unwrap();
+        File::create(subdir.join("nested").join("linked_file")).unwrap();
+        fs::set_permissions(&subdir, fs::Permissions::from_mode(0o700)).unwrap();
+        fs::set_permissions(subdir.join("nested"), fs::Permissions::from_mode(0o750)).unwrap();
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        resolve_all_with(
+            &mut io::sink(),
+            [symlink_path.clone()],
+            &Options {
+                preserve_dir_perms: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        let mode = |path: &Path| path.symlink_metadata().unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode(&symlink_path), 0o700);
+        assert_eq!(mode(&symlink_path.join("nested")), 0o750);
+        assert!(symlink_path.join("nested").join("linked_file").is_file());
+    }
+
+    #[test]
+    fn include_filter() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let lib_link = dir.join("libfoo.so");
+        let text_link = dir.join("notes.txt");
+        create_dir(&subdir).unwrap();
+        File::create(subdir.join("libfoo.so.1")).unwrap();
+        File::create(subdir.join("notes")).unwrap();
+        let _symlink = symlink(subdir.join("libfoo.so.1"), &lib_link);
+        let _symlink = symlink(subdir.join("notes"), &text_link);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [lib_link.clone(), text_link.clone()],
+            &Options {
+                no_summary: true,
+                include: vec![Pattern::new("*.so").unwrap()],
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "COPY {:?} => {lib_link:?}\nSKIP FILTER {text_link:?}\n",
+                subdir.join("libfoo.so.1")
+            )
+        );
+        assert!(!lib_link.is_symlink());
+        assert!(text_link.is_symlink());
+    }
+
+    #[test]
+    fn exclude_filter() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let skipped_dir = dir.join("skipped");
+        let linked_path = subdir.join("linked_file");
+        let kept_link = dir.join("kept");
+        let skipped_link = skipped_dir.join("link");
+        create_dir(&subdir).unwrap();
+        create_dir(&skipped_dir).unwrap();
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink(&linked_path, &kept_link);
+        let _symlink = symlink(&linked_path, &skipped_link);
+
+        let mut buffer = Vec::new();
+        let summary = resolve_all_with(
+            &mut buffer,
+            [dir.to_path_buf()],
+            &Options {
+                no_summary: true,
+                exclude: vec![Pattern::new("*/skipped/*").unwrap()],
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert!(
+            str::from_utf8(&buffer)
+                .unwrap()
+                .contains(&format!("SKIP FILTER {skipped_link:?}\n"))
+        );
+        assert_eq!(summary.copies, 1);
+        assert!(!kept_link.is_symlink());
+        assert!(skipped_link.is_symlink());
+    }
+
+    #[test]
+    fn shallow() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let linked_path = subdir.join("linked_file");
+        let nested_link = subdir.join("nested_link");
+        let symlink_path = dir.join("symlink");
+        create_dir(&subdir).unwrap();
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink("linked_file", &nested_link);
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone()],
+            &Options {
+                no_summary: true,
+                shallow: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert!(str::from_utf8(&buffer).unwrap().contains(&format!(
+            "SYMLINK \"linked_file\" => {:?}\n",
+            symlink_path.join("nested_link")
+        )));
+        assert!(symlink_path.is_dir() && !symlink_path.is_symlink());
+        assert!(symlink_path.join("linked_file").is_file());
+        assert_eq!(
+            read_link(symlink_path.join("nested_link")).unwrap(),
+            Path::new("linked_file")
+        );
+    }
+
+    #[test]
+    fn partial_failure() {
+        let dir = Temp::new_dir().unwrap();
+        let (_, bad_link, good_link) = failing_links(&dir);
+
+        let mut buffer = Vec::new();
+        let options = Options {
+            no_summary: true,
+            ..Options::default()
+        };
+        let mut ctx = Context::new(&mut buffer, &options);
+        ctx.copy = failing_copy;
+        ctx.exec([bad_link.clone(), good_link.clone()]).unwrap();
+
+        assert_eq!(ctx.summary.errors, 1);
+        assert!(good_link.is_file() && !good_link.is_symlink());
+    }
+
+    #[test]
+    fn parallel_jobs() {
+        let dir = Temp::new_dir().unwrap();
+
+        let mut links = Vec::new();
+        for idx in 0..8 {
+            let subdir = dir.join(format!("real_dir_{idx}"));
+            let symlink_path = dir.join(format!("symlink_{idx}"));
+            create_dir(&subdir).unwrap();
+            for name in ["a", "b", "c"] {
+                fs::write(subdir.join(name), format!("{idx}{name}")).unwrap();
+            }
+            let _symlink = symlink(&subdir, &symlink_path);
+            links.push(symlink_path);
+        }
+
+        let mut buffer = Vec::new();
+        let summary = resolve_all_with(
+            &mut buffer,
+            links.clone(),
+            &Options {
+                no_summary: true,
+                jobs: NonZeroUsize::new(4),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(summary.populated, 8);
+        assert_eq!(summary.copies, 24);
+        assert_eq!(summary.errors, 0);
+        let output = str::from_utf8(&buffer).unwrap();
+        assert_eq!(output.lines().count(), 32);
+        for (idx, link) in links.iter().enumerate() {
+            assert!(link.is_dir() && !link.is_symlink());
+            for name in ["a", "b", "c"] {
+                assert_eq!(
+                    fs::read_to_string(link.join(name)).unwrap(),
+                    format!("{idx}{name}")
+                );
+            }
+
+            // Each path's lines are written out together
+            let populate = format!(
+                "POPULATE {:?} => {link:?}",
+                dir.join(format!("real_dir_{idx}"))
+            );
+            let mut lines = output.lines().skip_while(|line| *line != populate);
+            assert_eq!(lines.next(), Some(populate.as_str()));
+            assert!(lines.take(3).all(|line| line.starts_with("COPY")));
+        }
+    }
+
+    #[test]
+    fn verbose() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        let plain_path = dir.join("plain_file");
+        File::create(&linked_path).unwrap();
+        File::create(&plain_path).unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone(), plain_path.clone()],
+            &Options {
+                no_summary: true,
+                verbose: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "RESOLVED {symlink_path:?} -> {linked_path:?}\nVISIT {symlink_path:?}\nCOPY {linked_path:?} => {symlink_path:?}\nVISIT {plain_path:?}\nSKIP NOT A LINK {plain_path:?}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn null_delimited_paths() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("sym\nlink");
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+
+        let mut input = symlink_path.as_os_str().as_encoded_bytes().to_vec();
+        input.push(b'\0');
+        let paths = read_paths(input.as_slice(), b'\0')
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(paths, [symlink_path.as_path()]);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            paths,
+            &Options {
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!("COPY {linked_path:?} => {symlink_path:?}\n")
+        );
+        assert!(symlink_path.is_file() && !symlink_path.is_symlink());
+    }
+
+    #[test]
+    fn comma_delimited_paths() {
+        let input = b"first,sec ond,last";
+        let paths = read_paths(input.as_slice(), b',')
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        // The unterminated last path is kept too
+        assert_eq!(
+            paths,
+            [Path::new("first"), Path::new("sec ond"), Path::new("last")]
+        );
+    }
+
+    #[test]
+    fn file_url() {
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.canonicalize().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("my link%");
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+
+        let url = format!(
+            "file://{}/my%20link%25",
+            dir.to_str().unwrap().replace('\\', "/")
+        );
+        #[cfg(windows)]
+        let url = url.replacen("file://", "file:///", 1);
+        let path = path_from_url(url.into()).unwrap();
+        assert_eq!(path, symlink_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [path],
+            &Options {
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!("COPY {linked_path:?} => {symlink_path:?}\n")
+        );
+
+        // Plain paths are kept as is
+        assert_eq!(path_from_url(symlink_path.clone()).unwrap(), symlink_path);
+        for url in ["https://example.com/file", "file://host/file", "file:///%2"] {
+            let e = path_from_url(url.into()).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::InvalidInput, "{url}");
+        }
+    }
+
+    #[test]
+    fn broken_link() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let broken_path = subdir.join("broken");
+        let symlink_path = dir.join("symlink");
+        create_dir(&subdir).unwrap();
+        let _symlink = symlink(dir.join("missing"), &broken_path);
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone(), subdir.clone()],
+            &Options {
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "POPULATE {subdir:?} => {symlink_path:?}\nSKIP BROKEN {:?}\nSKIP BROKEN {broken_path:?}\n",
+                symlink_path.join("broken")
+            )
+        );
+        assert!(broken_path.is_symlink());
+        assert_eq!(
+            read_link(symlink_path.join("broken")).unwrap(),
+            dir.join("missing")
+        );
+    }
+
+    #[test]
+    fn prune_broken() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let broken_path = subdir.join("broken");
+        let symlink_path = dir.join("symlink");
+        create_dir(&subdir).unwrap();
+        let _symlink = symlink(dir.join("missing"), &broken_path);
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone(), subdir.clone()],
+            &Options {
+                no_summary: true,
+                prune_broken: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "POPULATE {subdir:?} => {symlink_path:?}\nPRUNE BROKEN {:?}\nPRUNE BROKEN {broken_path:?}\n",
+                symlink_path.join("broken")
+            )
+        );
+        assert!(broken_path.symlink_metadata().is_err());
+        assert!(symlink_path.join("broken").symlink_metadata().is_err());
+    }
+
+    #[test]
+    fn no_canonicalize() {
+        for no_canonicalize in [false, true] {
+            let dir = Temp::new_dir().unwrap();
+
+            let real_path = dir.join("real_file");
+            let middle_path = dir.join("b");
+            let symlink_path = dir.join("a");
+            fs::write(&real_path, "contents").unwrap();
+            let _symlink = symlink("real_file", &middle_path);
+            let _symlink = symlink("b", &symlink_path);
+
+            let mut buffer = Vec::new();
+            resolve_all_with(
+                &mut buffer,
+                [symlink_path.clone()],
+                &Options {
+                    no_summary: true,
+                    no_canonicalize,
+                    ..Options::default()
+                },
+            )
+            .unwrap();
+            let output = str::from_utf8(&buffer).unwrap();
+
+            if no_canonicalize {
+                assert_eq!(
+                    output,
+                    format!("SYMLINK {real_path:?} => {symlink_path:?}\n")
+                );
+                assert_eq!(read_link(&symlink_path).unwrap(), real_path);
+            } else {
+                assert_eq!(output, format!("COPY {real_path:?} => {symlink_path:?}\n"));
+                assert!(!symlink_path.is_symlink());
+            }
+            assert_eq!(fs::read_to_string(&symlink_path).unwrap(), "contents");
+            assert!(middle_path.is_symlink());
+        }
+    }
+
+    #[test]
+    fn skip_unchanged() {
+        let dir = Temp::new_dir().unwrap();
+
+        let tree = dir.join("tree");
+        let source = dir.join("source");
+        let into = dir.join("out");
+        let mirrored = into.join("tree");
+        create_dir(&tree).unwrap();
+        create_dir(&source).unwrap();
+        let linked_path = source.join("linked_file");
+        fs::write(&linked_path, "contents").unwrap();
+        fs::write(tree.join("real_file"), "contents").unwrap();
+        let _symlink = symlink(&linked_path, tree.join("symlink"));
+
+        let options = Options {
+            into: Some(into.clone()),
+            merge: true,
+            no_summary: true,
+            ..Options::default()
+        };
+        let mut buffer = Vec::new();
+        resolve_all_with(&mut buffer, [tree.clone()], &options).unwrap();
+        let output = str::from_utf8(&buffer).unwrap();
+        assert!(!output.contains("SKIP UNCHANGED"));
+        assert_eq!(output.matches("COPY ").count(), 2);
+
+        // Nothing changed since the first run
+        buffer.clear();
+        resolve_all_with(&mut buffer, [tree.clone()], &options).unwrap();
+        let output = str::from_utf8(&buffer).unwrap();
+        for name in ["real_file", "symlink"] {
+            let unchanged = mirrored.join(name);
+            assert!(output.contains(&format!("SKIP UNCHANGED {unchanged:?}\n")));
+        }
+        assert!(!output.contains("COPY "));
+
+        // Differing contents are still replaced
+        fs::write(mirrored.join("symlink"), "changed").unwrap();
+        buffer.clear();
+        resolve_all_with(&mut buffer, [tree.clone()], &options).unwrap();
+        let output = str::from_utf8(&buffer).unwrap();
+        assert!(output.contains(&format!(
+            "COPY {:?} => {:?}\n",
+            tree.join("symlink"),
+            mirrored.join("symlink")
+        )));
+        assert_eq!(
+            fs::read_to_string(mirrored.join("symlink")).unwrap(),
+            "contents"
+        );
+        assert_eq!(read_dir(&mirrored).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn root_confinement() {
+        let dir = Temp::new_dir().unwrap();
+        let outside = Temp::new_dir().unwrap();
+
+        let inside_path = dir.join("inside_file");
+        let outside_path = outside.join("outside_file");
+        let inside_link = dir.join("inside_link");
+        let outside_link = dir.join("outside_link");
+        File::create(&inside_path).unwrap();
+        File::create(&outside_path).unwrap();
+        let _symlink = symlink(&inside_path, &inside_link);
+        let _symlink = symlink(&outside_path, &outside_link);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [inside_link.clone(), outside_link.clone()],
+            &Options {
+                no_summary: true,
+                root: Some(dir.to_path_buf()),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "COPY {inside_path:?} => {inside_link:?}\nSKIP OUTSIDE ROOT {outside_path:?}\n"
+            )
+        );
+        assert!(!inside_link.is_symlink());
+        assert!(outside_link.is_symlink());
+    }
+
+    #[test]
+    fn root_confinement_nested() {
+        let dir = Temp::new_dir().unwrap();
+        let outside = Temp::new_dir().unwrap();
+
+        let source = dir.join("source");
+        let dir_link = dir.join("dir_link");
+        let outside_path = outside.join("outside_file");
+        create_dir(&source).unwrap();
+        File::create(source.join("inside_file")).unwrap();
+        File::create(&outside_path).unwrap();
+        let _symlink = symlink(&outside_path, source.join("outside_link"));
+        let _symlink = symlink(&source, &dir_link);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [dir_link.clone()],
+            &Options {
+                no_summary: true,
+                root: Some(dir.to_path_buf()),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert!(
+            str::from_utf8(&buffer)
+                .unwrap()
+                .contains("SKIP OUTSIDE ROOT ")
+        );
+        assert!(!dir_link.is_symlink());
+        assert!(dir_link.join("inside_file").is_file());
+        let kept = dir_link.join("outside_link");
+        assert!(kept.is_symlink());
+        assert_eq!(read_link(&kept).unwrap(), outside_path);
+    }
+
+    #[test]
+    fn progress() {
+        struct Counter(Rc<Cell<(usize, usize)>>);
+
+        impl Progress for Counter {
+            fn start(&mut self, _total: usize) {}
+
+            fn update(&mut self, _summary: &Summary, _done: usize, _path: &Path) {
+                let (updates, finishes) = self.0.get();
+                self.0.set((updates + 1, finishes));
+            }
+
+            fn finish(&mut self) {
+                let (updates, finishes) = self.0.get();
+                self.0.set((updates, finishes + 1));
+            }
+        }
+
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let symlink_path = dir.join("symlink");
+        create_dir(&subdir).unwrap();
+        for name in ["a", "b", "c"] {
+            File::create(subdir.join(name)).unwrap();
+        }
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        let counts = Rc::new(Cell::new((0, 0)));
+        let mut buffer = Vec::new();
+        let options = Options {
+            no_summary: true,
+            progress: true,
+            ..Options::default()
+        };
+        let mut ctx = Context::new(&mut buffer, &options);
+        ctx.progress = Some(Box::new(Counter(counts.clone())));
+        ctx.exec([symlink_path.clone(), dir.join("missing")])
+            .unwrap();
+
+        // One populate, three copies, and one skip
+        assert_eq!(counts.get(), (5, 1));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn bare_relative_input() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink("linked_file", &symlink_path);
+
+        let cwd = env::current_dir().unwrap();
+        env::set_current_dir(&dir).unwrap();
+        let mut buffer = Vec::new();
+        let result = resolve_all_with(
+            &mut buffer,
+            [PathBuf::from("symlink")],
+            &Options {
+                no_summary: true,
+                ..Options::default()
+            },
+        );
+        env::set_current_dir(cwd).unwrap();
+
+        result.unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!("COPY {linked_path:?} => {symlink_path:?}\n")
+        );
+        assert!(symlink_path.is_file() && !symlink_path.is_symlink());
+    }
+
+    #[test]
+    fn interactive() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let declined_path = dir.join("declined");
+        let accepted_path = dir.join("accepted");
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink(&linked_path, &declined_path);
+        let _symlink = symlink(&linked_path, &accepted_path);
+
+        let mut buffer = Vec::new();
+        let options = Options {
+            no_summary: true,
+            interactive: true,
+            ..Options::default()
+        };
+        let mut ctx = Context::new(&mut buffer, &options);
+        ctx.answers = Some(Box::new(&b"n\nY\n"[..]));
+        ctx.exec([declined_path.clone(), accepted_path.clone()])
+            .unwrap();
+
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!("SKIP DECLINED {declined_path:?}\nCOPY {linked_path:?} => {accepted_path:?}\n")
+        );
+        assert!(declined_path.is_symlink());
+        assert!(!accepted_path.is_symlink());
+    }
+
+    #[test]
+    fn dedup() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let links = ["a", "b", "c"].map(|name| dir.join(name));
+        fs::write(&linked_path, "contents").unwrap();
+        for link in &links {
+            let _symlink = symlink(&linked_path, link);
+        }
+
+        let mut buffer = Vec::new();
+        let summary = resolve_all_with(
+            &mut buffer,
+            links.clone(),
+            &Options {
+                no_summary: true,
+                dedup: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "COPY {linked_path:?} => {:?}\nHARDLINK {:?} => {:?}\nHARDLINK {:?} => {:?}\n",
+                links[0], links[0], links[1], links[0], links[2]
+            )
+        );
+        assert_eq!((summary.copies, summary.hardlinks), (1, 2));
+        for link in &links {
+            assert!(!link.is_symlink());
+            assert_eq!(fs::read_to_string(link).unwrap(), "contents");
+        }
+        #[cfg(unix)]
+        {
+            assert_eq!(links[0].metadata().unwrap().nlink(), 3);
+            assert_eq!(linked_path.metadata().unwrap().nlink(), 1);
+        }
+    }
+
+    #[test]
+    fn dedup_through_links() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let symlink_path = dir.join("symlink");
+        create_dir(&subdir).unwrap();
+        fs::write(subdir.join("linked_file"), "contents").unwrap();
+        // Reached through the other link
+        let _symlink = symlink(subdir.join("linked_file"), subdir.join("first"));
+        let _symlink = symlink(subdir.join("first"), subdir.join("second"));
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        let mut buffer = Vec::new();
+        let summary = resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone()],
+            &Options {
+                no_summary: true,
+                dedup: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!((summary.copies, summary.hardlinks), (1, 2));
+        for name in ["linked_file", "first", "second"] {
+            let copy = symlink_path.join(name);
+            assert!(!copy.is_symlink());
+            assert_eq!(fs::read_to_string(&copy).unwrap(), "contents");
+        }
+        #[cfg(unix)]
+        assert_eq!(symlink_path.join("second").metadata().unwrap().nlink(), 3);
+    }
+
+    #[test]
+    fn exclude_from() {
+        let dir = Temp::new_dir().unwrap();
+        let list = Temp::new_file().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let links = ["a", "b", "c"].map(|name| dir.join(name));
+        File::create(&linked_path).unwrap();
+        for link in &links {
+            let _symlink = symlink(&linked_path, link);
+        }
+        // Unnormalized, to check it is compared by location
+        fs::write(&list, format!("{}\n", dir.join(".").join("b").display())).unwrap();
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            links.clone(),
+            &Options {
+                no_summary: true,
+                exclude_from: Some(list.to_path_buf()),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "COPY {linked_path:?} => {:?}\nSKIP FILTER {:?}\nCOPY {linked_path:?} => {:?}\n",
+                links[0], links[1], links[2]
+            )
+        );
+        assert!(links[1].is_symlink());
+    }
+
+    #[test]
+    fn summary_bytes() {
+        for dry_run in [false, true] {
+            let dir = Temp::new_dir().unwrap();
+
+            let small_path = dir.join("small");
+            let large_path = dir.join("large");
+            let small_link = dir.join("small_link");
+            let large_link = dir.join("large_link");
+            fs::write(&small_path, [0; 10]).unwrap();
+            fs::write(&large_path, [0; 2048]).unwrap();
+            let _symlink = symlink(&small_path, &small_link);
+            let _symlink = symlink(&large_path, &large_link);
+
+            let mut buffer = Vec::new();
+            let summary = resolve_all_with(
+                &mut buffer,
+                [small_link, large_link],
+                &Options {
+                    dry_run,
+                    ..Options::default()
+                },
+            )
+            .unwrap();
+            assert_eq!(summary.bytes, 2058);
+            assert!(
+                str::from_utf8(&buffer)
+                    .unwrap()
+                    .ends_with(" bytes=2058 distinct_targets=2\n")
+            );
+        }
+    }
+
+    #[test]
+    fn log_template() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone(), dir.join("missing")],
+            &Options {
+                no_summary: true,
+                log_template: Some("{action}|{link}|{dest}|{other}".to_string()),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "COPY|{symlink_path:?}|{linked_path:?}|{{other}}\nSKIP INPUT|{:?}||{{other}}\n",
+                dir.join("missing")
+            )
+        );
+    }
+
+    #[test]
+    fn relativize() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let linked_path = subdir.join("linked_file");
+        let link_dir = dir.join("links");
+        let symlink_path = link_dir.join("symlink");
+        let relative_link = link_dir.join("relative");
+        create_dir(&subdir).unwrap();
+        create_dir(&link_dir).unwrap();
+        fs::write(&linked_path, "contents").unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+        let _symlink = symlink("symlink", &relative_link);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [dir.to_path_buf()],
+            &Options {
+                no_summary: true,
+                relativize: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        let relative = Path::new("..").join("real_dir").join("linked_file");
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!("RELATIVIZE {symlink_path:?} => {relative:?}\n")
+        );
+        assert_eq!(read_link(&symlink_path).unwrap(), relative);
+        assert_eq!(fs::read_to_string(&symlink_path).unwrap(), "contents");
+        assert_eq!(read_link(&relative_link).unwrap(), Path::new("symlink"));
+    }
+
+    #[test]
+    fn absolutize() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let relative_link = dir.join("relative");
+        let absolute_link = dir.join("absolute");
+        fs::write(&linked_path, "contents").unwrap();
+        let _symlink = symlink("linked_file", &relative_link);
+        let _symlink = symlink(&linked_path, &absolute_link);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [relative_link.clone(), absolute_link.clone()],
+            &Options {
+                no_summary: true,
+                absolutize: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!("ABSOLUTIZE {relative_link:?} => {linked_path:?}\n")
+        );
+        assert_eq!(read_link(&relative_link).unwrap(), linked_path);
+        assert_eq!(fs::read_to_string(&relative_link).unwrap(), "contents");
+        assert_eq!(read_link(&absolute_link).unwrap(), linked_path);
+    }
+
+    #[test]
+    fn permission_denied_entry() {
+        fn denied_copy(src: &Path, dest: &Path) -> io::Result<u64> {
+            if src.file_name() == Some("denied".as_ref()) {
+                Err(ErrorKind::PermissionDenied.into())
+            } else {
+                copy_file(src, dest)
+            }
+        }
+
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let symlink_path = dir.join("symlink");
+        create_dir(&subdir).unwrap();
+        for name in ["a", "denied", "b"] {
+            File::create(subdir.join(name)).unwrap();
+        }
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        let mut buffer = Vec::new();
+        let options = Options {
+            no_summary: true,
+            ..Options::default()
+        };
+        let mut ctx = Context::new(&mut buffer, &options);
+        ctx.copy = denied_copy;
+        ctx.exec([symlink_path.clone()]).unwrap();
+
+        assert_eq!(ctx.summary.errors, 1);
+        assert!(symlink_path.is_dir() && !symlink_path.is_symlink());
+        assert!(symlink_path.join("a").is_file());
+        assert!(symlink_path.join("b").is_file());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn permission_denied_dir() {
+        let dir = Temp::new_dir().unwrap();
+
+        let locked_dir = dir.join("locked");
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        create_dir(&locked_dir).unwrap();
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+        fs::set_permissions(&locked_dir, fs::Permissions::from_mode(0o000)).unwrap();
+        // Permissions do not restrict privileged users
+        if read_dir(&locked_dir).is_ok() {
+            return;
+        }
+
+        let summary = resolve_all_with(&mut io::sink(), [dir.to_path_buf()], &Options::default());
+        fs::set_permissions(&locked_dir, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let summary = summary.unwrap();
+        assert_eq!(summary.errors, 1);
+        assert_eq!(summary.copies, 1);
+        assert!(!symlink_path.is_symlink());
+    }
+
+    #[test]
+    fn stats_json() {
+        let summary = Summary {
+            copies: 3,
+            bytes: 1024,
+            elapsed: Duration::from_millis(1500),
+            ..Summary::default()
+        };
+        let value: serde_json::Value = serde_json::from_str(&summary.stats_json()).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "copies": 3,
+                "hardlinks": 0,
+                "moves": 0,
+                "populated": 0,
+                "skipped": 0,
+                "errors": 0,
+                "bytes": 1024,
+                "distinct_targets": 0,
+                "elapsed_secs": 1.5,
+            })
+        );
+    }
+
+    #[test]
+    fn file_output() {
+        let dir = Temp::new_dir().unwrap();
+        let output_path = Temp::new_file().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+
+        resolve_all_with(
+            &mut File::create(&output_path).unwrap(),
+            [symlink_path.clone()],
+            &Options {
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(&output_path).unwrap(),
+            format!("COPY {linked_path:?} => {symlink_path:?}\n")
+        );
+    }
+
+    #[test]
+    fn contained_input() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let subdir = dir.join("real_dir");
+        let symlink_path = subdir.join("symlink");
+        File::create(&linked_path).unwrap();
+        create_dir(&subdir).unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone(), dir.to_path_buf(), dir.to_path_buf()],
+            &Options {
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "SKIP CONTAINED {symlink_path:?}\nCOPY {linked_path:?} => {symlink_path:?}\nSKIP CONTAINED {:?}\n",
+                dir.to_path_buf()
+            )
+        );
+    }
+
+    #[test]
+    fn move_files() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        fs::write(&linked_path, "contents").unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone()],
+            &Options {
+                move_files: true,
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!("MOVE {linked_path:?} => {symlink_path:?}\n")
+        );
+
+        assert!(!symlink_path.is_symlink());
+        assert_eq!(fs::read_to_string(&symlink_path).unwrap(), "contents");
+        assert!(!linked_path.exists());
+    }
+
+    #[test]
+    fn move_shared_file() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        let other_symlink_path = dir.join("other_symlink");
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+        let _other_symlink = symlink(&linked_path, &other_symlink_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone(), other_symlink_path.clone()],
+            &Options {
+                move_files: true,
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "COPY {linked_path:?} => {symlink_path:?}\nCOPY {linked_path:?} => {other_symlink_path:?}\n"
+            )
+        );
+        assert!(linked_path.exists());
+    }
+
+    #[test]
+    fn link_chain_too_long() {
+        let dir = Temp::new_dir().unwrap();
+
+        // Longer than any platform follows, without looping
+        let chain_dir = dir.join("chain");
+        let subdir = dir.join("real_dir");
+        create_dir(&chain_dir).unwrap();
+        create_dir(&subdir).unwrap();
+        let linked_path = chain_dir.join("linked_file");
+        File::create(&linked_path).unwrap();
+        let mut target = linked_path;
+        let mut links = Vec::new();
+        for idx in 0..100 {
+            let link = chain_dir.join(format!("symlink_{idx}"));
+            links.push(symlink(&target, &link));
+            target = link;
+        }
+        let symlink_path = subdir.join("symlink");
+        let _symlink = symlink(&target, &symlink_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [subdir.clone()],
+            &Options {
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!("SKIP SELF REFERENCE {symlink_path:?}\n")
+        );
+
+        assert!(is_filesystem_loop(&File::open(&symlink_path).unwrap_err()));
+    }
+
+    #[test]
+    fn on_error_skip() {
+        let dir = Temp::new_dir().unwrap();
+        let (_, bad_link, good_link) = failing_links(&dir);
+
+        let mut buffer = Vec::new();
+        let options = Options {
+            no_summary: true,
+            on_error: OnError::Skip,
+            ..Options::default()
+        };
+        let mut ctx = Context::new(&mut buffer, &options);
+        ctx.copy = failing_copy;
+        ctx.exec([bad_link.clone(), good_link.clone()]).unwrap();
+
+        assert_eq!(ctx.summary.errors, 1);
+        assert!(good_link.is_file() && !good_link.is_symlink());
+    }
+
+    #[test]
+    fn on_error_abort() {
+        let dir = Temp::new_dir().unwrap();
+        let stderr = Temp::new_file().unwrap();
+        let (bad_path, bad_link, good_link) = failing_links(&dir);
+
+        let mut buffer = Vec::new();
+        let options = Options {
+            no_summary: true,
+            on_error: OnError::Abort,
+            ..Options::default()
+        };
+        let mut ctx = Context::new(&mut buffer, &options);
+        ctx.copy = failing_copy;
+        ctx.errors = Box::new(File::create(&stderr).unwrap());
+        let e = ctx.exec([bad_link.clone(), good_link.clone()]).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            format!("Cannot copy {bad_path:?} to {bad_link:?}: injected failure")
+        );
+        assert_eq!(DelinkError::from_io(&e).unwrap().path(), bad_link);
+        assert_eq!(ctx.summary.errors, 1);
+        drop(ctx);
+
+        // Nothing after the failure is attempted, and reporting it is left to
+        // the caller
+        assert!(!str::from_utf8(&buffer).unwrap().contains("good"));
+        assert_eq!(fs::read_to_string(&stderr).unwrap(), "");
+        assert!(bad_link.is_symlink());
+        assert!(good_link.is_symlink());
+    }
+
+    #[test]
+    fn logical_resolution() {
+        let dir = Temp::new_dir().unwrap();
+
+        // "via/../file" is "file" logically, but "real/nested/../file"
+        // physically.
+        let nested = dir.join("real").join("nested");
+        let via = dir.join("via");
+        let logical_file = dir.join("file");
+        let physical_file = dir.join("real").join("file");
+        create_dir_all(&nested).unwrap();
+        let _via = symlink(&nested, &via);
+        fs::write(&logical_file, "logical").unwrap();
+        fs::write(&physical_file, "physical").unwrap();
+
+        for (logical, expected) in [(true, &logical_file), (false, &physical_file)] {
+            let symlink_path = dir.join("symlink");
+            let _ = remove_file(&symlink_path);
+            let _symlink = symlink(Path::new("via").join("..").join("file"), &symlink_path);
+
+            let mut buffer = Vec::new();
+            resolve_all_with(
+                &mut buffer,
+                [symlink_path.clone()],
+                &Options {
+                    logical,
+                    no_summary: true,
+                    ..Options::default()
+                },
+            )
+            .unwrap();
+            assert_eq!(
+                str::from_utf8(&buffer).unwrap(),
+                format!(
+                    "COPY {:?} => {symlink_path:?}\n",
+                    canonicalize(expected).unwrap()
+                )
+            );
+            assert_eq!(
+                fs::read(&symlink_path).unwrap(),
+                fs::read(expected).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn dir_link_trailing_separator() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let symlink_path = dir.join("symlink");
+        let linked_path = dir.join("linked_file");
+        let inner_link = subdir.join("inner_link");
+        create_dir(&subdir).unwrap();
+        File::create(&linked_path).unwrap();
+        let _inner_link = symlink(&linked_path, &inner_link);
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        let mut input = symlink_path.clone().into_os_string();
+        input.push(path::MAIN_SEPARATOR_STR);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [PathBuf::from(input)],
+            &Options {
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "POPULATE {subdir:?} => {symlink_path:?}\nCOPY {inner_link:?} => {:?}\n",
+                symlink_path.join("inner_link")
+            )
+        );
+
+        assert!(symlink_path.is_dir() && !symlink_path.is_symlink());
+        // The linked directory itself is left alone
+        assert!(inner_link.is_symlink());
+    }
+
+    #[test]
+    fn count_only() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let symlink_path = dir.join("symlink");
+        create_dir(&subdir).unwrap();
+        fs::write(subdir.join("linked_file"), "contents").unwrap();
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone()],
+            &Options {
+                count_only: true,
+                no_summary: true,
+                verbose: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            "SUMMARY copies=1 hardlinks=0 moves=0 populated=1 skipped=0 errors=0 bytes=8 distinct_targets=2\n"
+        );
+        assert!(symlink_path.is_dir() && !symlink_path.is_symlink());
+    }
+
+    #[test]
+    fn preserve_link_times() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink(canonicalize(&linked_path).unwrap(), &symlink_path);
+
+        // Backdate the link, so recreating it now would be noticed
+        let old = std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        File::options()
+            .write(true)
+            .open(&linked_path)
+            .unwrap()
+            .set_times(FileTimes::new().set_accessed(old).set_modified(old))
+            .unwrap();
+        set_link_times(&symlink_path, &linked_path.metadata().unwrap()).unwrap();
+        let original = symlink_path.symlink_metadata().unwrap().modified().unwrap();
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone()],
+            &Options {
+                relativize: true,
+                preserve_link_times: true,
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert!(read_link(&symlink_path).unwrap().is_relative());
+
+        let recreated = symlink_path.symlink_metadata().unwrap().modified().unwrap();
+        let difference = recreated
+            .duration_since(original)
+            .unwrap_or_else(|e| e.duration());
+        assert!(difference < Duration::from_secs(1), "{difference:?}");
+    }
+
+    #[test]
+    fn max_total_bytes() {
+        let dir = Temp::new_dir().unwrap();
+
+        let first_path = dir.join("first");
+        let second_path = dir.join("second");
+        let first_link = dir.join("first_link");
+        let second_link = dir.join("second_link");
+        fs::write(&first_path, "contents").unwrap();
+        fs::write(&second_path, "contents").unwrap();
+        let _symlink = symlink(&first_path, &first_link);
+        let _symlink = symlink(&second_path, &second_link);
+
+        let mut buffer = Vec::new();
+        let options = Options {
+            max_total_bytes: Some(10),
+            no_summary: true,
+            ..Options::default()
+        };
+        let mut ctx = Context::new(&mut buffer, &options);
+        let e = ctx
+            .exec([first_link.clone(), second_link.clone()])
+            .unwrap_err();
+        assert_eq!(DelinkError::from_io(&e).unwrap().path(), second_link);
+        assert_eq!(ctx.summary.bytes, 8);
+        assert_eq!(ctx.summary.errors, 1);
+
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!("COPY {first_path:?} => {first_link:?}\nABORT BYTE LIMIT {second_link:?}\n")
+        );
+        assert!(!first_link.is_symlink());
+        assert!(second_link.is_symlink());
+    }
+
+    #[test]
+    fn partial_summary() {
+        let dir = Temp::new_dir().unwrap();
+
+        let first_path = dir.join("first");
+        let second_path = dir.join("second");
+        let first_link = dir.join("first_link");
+        let second_link = dir.join("second_link");
+        fs::write(&first_path, "contents").unwrap();
+        fs::write(&second_path, "contents").unwrap();
+        let _symlink = symlink(&first_path, &first_link);
+        let _symlink = symlink(&second_path, &second_link);
+
+        let (summary, result) = resolve_all_with_summary(
+            &mut io::sink(),
+            [first_link, second_link],
+            &Options {
+                max_total_bytes: Some(10),
+                dry_run: true,
+                ..Options::default()
+            },
+        );
+        assert!(result.is_err());
+        assert_eq!((summary.copies, summary.errors, summary.bytes), (1, 1, 8));
+    }
+
+    #[test]
+    fn tree_jobs_max_total_bytes() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let symlink_path = dir.join("symlink");
+        create_dir(&subdir).unwrap();
+        for name in ["a", "b", "c", "d", "e", "f", "g", "h"] {
+            fs::write(subdir.join(name), [0; 100]).unwrap();
+        }
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        let mut buffer = Vec::new();
+        let options = Options {
+            max_total_bytes: Some(250),
+            tree_jobs: NonZeroUsize::new(4),
+            no_summary: true,
+            ..Options::default()
+        };
+        let mut ctx = Context::new(&mut buffer, &options);
+        assert!(ctx.exec([symlink_path.clone()]).is_err());
+        assert_eq!(ctx.summary.bytes, 200);
+        assert_eq!(ctx.summary.errors, 1);
+
+        let output = str::from_utf8(&buffer).unwrap();
+        assert_eq!(output.matches("COPY ").count(), 2);
+        assert_eq!(output.matches("ABORT BYTE LIMIT ").count(), 1);
+        // Nothing is left half populated
+        assert!(symlink_path.is_symlink());
+    }
+
+    #[test]
+    fn populated_source_links() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let symlink_path = dir.join("symlink");
+        create_dir(&subdir).unwrap();
+        let _self_link = symlink("self", subdir.join("self"));
+        let _up_link = symlink("..", subdir.join("up"));
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone()],
+            &Options {
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        let mut lines: Vec<_> = str::from_utf8(&buffer).unwrap().lines().collect();
+        // Entry order within the directory is not fixed
+        lines[1..].sort();
+        assert_eq!(
+            lines,
+            [
+                format!("POPULATE {subdir:?} => {symlink_path:?}"),
+                format!("SKIP RECURSIVE {:?}", symlink_path.join("up")),
+                format!("SKIP SELF REFERENCE {:?}", symlink_path.join("self")),
+            ]
+        );
+
+        assert!(symlink_path.is_dir() && !symlink_path.is_symlink());
+        assert_eq!(
+            read_link(symlink_path.join("self")).unwrap(),
+            Path::new("self")
+        );
+        assert!(symlink_path.join("up").is_symlink());
+    }
+
+    #[test]
+    fn tree_jobs() {
+        /// Relative path and contents of everything under `dir`, sorted.
+        fn tree(dir: &Path) -> Vec<(PathBuf, Option<Vec<u8>>)> {
+            let mut entries = Vec::new();
+            let mut stack = vec![dir.to_path_buf()];
+            while let Some(path) = stack.pop() {
+                for entry in read_dir(&path).unwrap() {
+                    let entry = entry.unwrap().path();
+                    let relative = entry.strip_prefix(dir).unwrap().to_path_buf();
+                    if entry.is_symlink() {
+                        entries.push((
+                            relative,
+                            Some(
+                                read_link(&entry)
+                                    .unwrap()
+                                    .into_os_string()
+                                    .into_encoded_bytes(),
+                            ),
+                        ));
+                    } else if entry.is_dir() {
+                        entries.push((relative, None));
+                        stack.push(entry);
+                    } else {
+                        entries.push((relative, Some(fs::read(&entry).unwrap())));
+                    }
+                }
+            }
+            entries.sort();
+            entries
+        }
+
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        create_dir(&subdir).unwrap();
+        for idx in 0..16 {
+            let nested = subdir.join(format!("nested_{idx}"));
+            create_dir(&nested).unwrap();
+            for file_idx in 0..8 {
+                fs::write(
+                    nested.join(format!("file_{file_idx}")),
+                    format!("{idx} {file_idx}"),
+                )
+                .unwrap();
+            }
+            fs::write(subdir.join(format!("file_{idx}")), idx.to_string()).unwrap();
+        }
+        let _inner = symlink(subdir.join("file_0"), subdir.join("nested_0").join("inner"));
+
+        let mut outputs = Vec::new();
+        let mut trees = Vec::new();
+        for tree_jobs in [None, NonZeroUsize::new(4)] {
+            let symlink_path = dir.join("symlink");
+            let _symlink = symlink(&subdir, &symlink_path);
+
+            let mut buffer = Vec::new();
+            let summary = resolve_all_with(
+                &mut buffer,
+                [symlink_path.clone()],
+                &Options {
+                    tree_jobs,
+                    no_summary: true,
+                    ..Options::default()
+                },
+            )
+            .unwrap();
+            assert_eq!((summary.copies, summary.populated), (16 * 8 + 16 + 1, 17));
+
+            outputs.push(buffer);
+            trees.push(tree(&symlink_path));
+            fs::remove_dir_all(&symlink_path).unwrap();
+        }
+
+        assert_eq!(str::from_utf8(&outputs[0]), str::from_utf8(&outputs[1]));
+        assert_eq!(trees[0], trees[1]);
+    }
+
+    #[test]
+    fn show_target() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink("linked_file", &symlink_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone()],
+            &Options {
+                show_target: true,
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "COPY {:?} (was -> \"linked_file\") => {symlink_path:?}\n",
+                canonicalize(&linked_path).unwrap()
+            )
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn special_files() {
+        use std::{ffi::CString, os::unix::fs::FileTypeExt};
+
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let fifo_path = subdir.join("fifo");
+        create_dir(&subdir).unwrap();
+        let fifo = CString::new(fifo_path.as_os_str().as_encoded_bytes()).unwrap();
+        // SAFETY: `fifo` is NUL terminated.
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+
+        for recreate_special in [false, true] {
+            let symlink_path = dir.join("symlink");
+            let _symlink = symlink(&subdir, &symlink_path);
+
+            let mut buffer = Vec::new();
+            resolve_all_with(
+                &mut buffer,
+                [symlink_path.clone()],
+                &Options {
+                    recreate_special,
+                    no_summary: true,
+                    ..Options::default()
+                },
+            )
+            .unwrap();
+
+            let inner_path = symlink_path.join("fifo");
+            let expected = if recreate_special {
+                format!("MKNOD {fifo_path:?} => {inner_path:?}")
+            } else {
+                format!("SKIP SPECIAL {inner_path:?}")
+            };
+            assert_eq!(
+                str::from_utf8(&buffer).unwrap(),
+                format!("POPULATE {subdir:?} => {symlink_path:?}\n{expected}\n")
+            );
+            assert!(symlink_path.is_dir() && !symlink_path.is_symlink());
+            assert_eq!(
+                inner_path
+                    .symlink_metadata()
+                    .is_ok_and(|metadata| metadata.file_type().is_fifo()),
+                recreate_special
+            );
+            fs::remove_dir_all(&symlink_path).unwrap();
+        }
+    }
+
+    #[test]
+    fn only_broken() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let valid_path = dir.join("valid");
+        let broken_path = dir.join("broken");
+        File::create(&linked_path).unwrap();
+        let _valid = symlink(&linked_path, &valid_path);
+        let _broken = symlink(dir.join("missing"), &broken_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [dir.to_path_buf()],
+            &Options {
+                only_broken: true,
+                prune_broken: true,
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        let mut lines: Vec<_> = str::from_utf8(&buffer).unwrap().lines().collect();
+        // Entry order within the directory is not fixed
+        lines.sort();
+        assert_eq!(
+            lines,
+            [
+                format!("PRUNE BROKEN {broken_path:?}"),
+                format!("SKIP VALID {valid_path:?}"),
+            ]
+        );
+
+        assert!(broken_path.symlink_metadata().is_err());
+        assert!(valid_path.is_symlink());
+    }
+
+    #[test]
+    fn relative_output() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let subdir = dir.join("real_dir");
+        let symlink_path = subdir.join("symlink");
+        File::create(&linked_path).unwrap();
+        create_dir(&subdir).unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone()],
+            &Options {
+                relative_to: Some(subdir.clone()),
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "COPY {:?} => \"symlink\"\n",
+                Path::new("..").join("linked_file")
+            )
+        );
+        assert!(!symlink_path.is_symlink());
+    }
+
+    #[test]
+    fn diff_changed_text() {
+        let dir = Temp::new_dir().unwrap();
+
+        let tree = dir.join("tree");
+        let into = dir.join("out");
+        let linked_path = tree.join("file");
+        let existing = into.join("tree").join("file");
+        create_dir(&tree).unwrap();
+        create_dir_all(into.join("tree")).unwrap();
+        fs::write(&linked_path, "same\nnew\n").unwrap();
+        // Left by an earlier run
+        fs::write(&existing, "same\nold\n").unwrap();
+        let _symlink = symlink(&linked_path, tree.join("symlink"));
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [tree.clone()],
+            &Options {
+                dry_run: true,
+                diff: true,
+                into: Some(into.clone()),
+                merge: true,
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        let output = str::from_utf8(&buffer).unwrap();
+        assert!(output.contains(&format!(
+            "COPY {linked_path:?} => {existing:?}\n\
+             --- {existing:?}\n+++ {linked_path:?}\n\
+             @@ -1,2 +1,2 @@\n same\n-old\n+new\n"
+        )));
+        // Nothing was there to overwrite
+        let new_copy = into.join("tree").join("symlink");
+        assert!(output.contains(&format!(
+            "COPY {:?} => {new_copy:?}\n",
+            tree.join("symlink")
+        )));
+        assert_eq!(output.matches("--- ").count(), 1);
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "same\nold\n");
+    }
+
+    #[test]
+    fn extension_allowlist() {
+        let dir = Temp::new_dir().unwrap();
+
+        let library = dir.join("libfoo.so.1");
+        let text = dir.join("notes.txt");
+        fs::write(&library, "library").unwrap();
+        fs::write(&text, "text").unwrap();
+        let library_link = dir.join("libfoo.so");
+        let text_link = dir.join("notes.so");
+        let _library_link = symlink(&library, &library_link);
+        let _text_link = symlink(&text, &text_link);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [library_link.clone(), text_link.clone()],
+            &Options {
+                extensions: vec!["so".into(), "1".into()],
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "COPY {:?} => {library_link:?}\nSKIP EXT {text_link:?}\n",
+                canonicalize(&library).unwrap()
+            )
+        );
+        assert!(!library_link.is_symlink());
+        assert!(text_link.is_symlink());
+    }
+
+    #[test]
+    fn dangling_input() {
+        let dir = Temp::new_dir().unwrap();
+
+        let deleted_path = dir.join("deleted");
+        let symlink_path = dir.join("symlink");
+        let chain_path = dir.join("chain");
+        File::create(&deleted_path).unwrap();
+        let _symlink = symlink(&deleted_path, &symlink_path);
+        let _chain = symlink(&symlink_path, &chain_path);
+        remove_file(&deleted_path).unwrap();
+
+        let mut buffer = Vec::new();
+        resolve_all(&mut buffer, [symlink_path.clone(), chain_path.clone()]).unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "SKIP DANGLING {symlink_path:?}\nSKIP DANGLING {chain_path:?}\nSUMMARY copies=0 hardlinks=0 moves=0 populated=0 skipped=2 errors=0 bytes=0 distinct_targets=0\n"
+            )
+        );
+        assert!(symlink_path.is_symlink());
+        assert!(chain_path.is_symlink());
+    }
+
+    #[test]
+    fn verify_mixed_tree() {
+        let dir = Temp::new_dir().unwrap();
+
+        let tree = dir.join("tree");
+        let source = dir.join("source");
+        let broken_path = tree.join("nested").join("broken");
+        let fail_link = dir.join("fail_link");
+        create_dir_all(tree.join("nested")).unwrap();
+        create_dir(&source).unwrap();
+        fs::write(source.join("file"), "file").unwrap();
+        create_dir(dir.join("fail_dir")).unwrap();
+        fs::write(dir.join("fail_dir").join("fail"), "fail").unwrap();
+        let _symlink = symlink(source.join("file"), tree.join("file_link"));
+        let _symlink = symlink(&source, tree.join("dir_link"));
+        let _symlink = symlink(dir.join("missing"), &broken_path);
+        let _symlink = symlink(dir.join("fail_dir"), &fail_link);
+
+        // The broken link is kept on purpose, but the failed population is
+        // not
+        for (inputs, expected) in [
+            (vec![tree.clone()], "VERIFY OK\n".to_string()),
+            (
+                vec![tree.clone(), fail_link.clone()],
+                format!("VERIFY LEFTOVER {fail_link:?}\n"),
+            ),
+        ] {
+            let mut buffer = Vec::new();
+            let options = Options {
+                verify: true,
+                no_summary: true,
+                ..Options::default()
+            };
+            let mut ctx = Context::new(&mut buffer, &options);
+            ctx.copy = failing_copy;
+            ctx.exec(inputs).unwrap();
+            let output = str::from_utf8(&buffer).unwrap();
+            assert!(output.ends_with(&expected), "{output}");
+        }
+        assert!(broken_path.is_symlink());
+        assert!(!tree.join("file_link").is_symlink());
+        assert!(!tree.join("dir_link").is_symlink());
+    }
+
+    #[test]
+    fn reflink_fallback() {
+        let dir = Temp::new_dir().unwrap();
+
+        // Sparse, on filesystems that support it
+        let linked_path = dir.join("sparse");
+        let file = File::create(&linked_path).unwrap();
+        file.set_len(1024 * 1024).unwrap();
+        drop(file);
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&linked_path)
+            .unwrap()
+            .write_all(b"tail")
+            .unwrap();
+
+        for reflink in [Reflink::Auto, Reflink::Never] {
+            let symlink_path = dir.join("symlink");
+            let _ = remove_file(&symlink_path);
+            let _symlink = symlink(&linked_path, &symlink_path);
+
+            let summary = resolve_all_with(
+                &mut io::sink(),
+                [symlink_path.clone()],
+                &Options {
+                    reflink,
+                    ..Options::default()
+                },
+            )
+            .unwrap();
+            assert_eq!(summary.errors, 0);
+            assert!(!symlink_path.is_symlink());
+            assert_eq!(
+                fs::read(&symlink_path).unwrap(),
+                fs::read(&linked_path).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn breadth_first_order() {
+        let dir = Temp::new_dir().unwrap();
+
+        let tree = dir.join("tree");
+        let linked_path = dir.join("linked_file");
+        File::create(&linked_path).unwrap();
+        for sub in ["a", "b"] {
+            let deeper = tree.join(sub).join("deeper");
+            create_dir_all(&deeper).unwrap();
+            let _symlink = symlink(&linked_path, tree.join(sub).join("link"));
+            let _symlink = symlink(&linked_path, deeper.join("link"));
+        }
+
+        for breadth_first in [false, true] {
+            let mut buffer = Vec::new();
+            resolve_all_with(
+                &mut buffer,
+                [tree.clone()],
+                &Options {
+                    breadth_first,
+                    dry_run: true,
+                    no_summary: true,
+                    ..Options::default()
+                },
+            )
+            .unwrap();
+
+            // Entries may be listed in any order, but depth-first always
+            // finishes the first subdirectory before the second.
+            let depths: Vec<_> = str::from_utf8(&buffer)
+                .unwrap()
+                .lines()
+                .map(|line| line.matches(path::MAIN_SEPARATOR).count())
+                .collect();
+            assert_eq!(depths.len(), 4);
+            assert_eq!(depths.is_sorted(), breadth_first, "{depths:?}");
+        }
+    }
+
+    #[test]
+    fn no_recurse() {
+        let dir = Temp::new_dir().unwrap();
+
+        let tree = dir.join("tree");
+        let subdir = tree.join("subdir");
+        let source = dir.join("source");
+        let linked_path = dir.join("linked_file");
+        create_dir_all(&subdir).unwrap();
+        create_dir_all(source.join("nested")).unwrap();
+        File::create(&linked_path).unwrap();
+        File::create(source.join("nested").join("file")).unwrap();
+        let top_link = tree.join("top_link");
+        let inner_link = subdir.join("inner_link");
+        let dir_link = tree.join("dir_link");
+        let _symlink = symlink(&linked_path, &top_link);
+        let _symlink = symlink(&linked_path, &inner_link);
+        let _symlink = symlink(&source, &dir_link);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [tree.clone()],
+            &Options {
+                no_recurse: true,
+                verify: true,
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert!(str::from_utf8(&buffer).unwrap().ends_with("VERIFY OK\n"));
+        assert!(top_link.is_file() && !top_link.is_symlink());
+        assert!(inner_link.is_symlink());
+        // Populated all the way down
+        assert!(dir_link.join("nested").join("file").is_file());
+        assert!(!dir_link.is_symlink());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn preserve_owner() {
+        // SAFETY: Only reads the effective user of this process.
+        let is_root = unsafe { libc::geteuid() } == 0;
+
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let linked_path = subdir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        create_dir(&subdir).unwrap();
+        File::create(&linked_path).unwrap();
+        if is_root {
+            std::os::unix::fs::chown(&subdir, Some(1234), Some(5678)).unwrap();
+            std::os::unix::fs::chown(&linked_path, Some(4321), Some(8765)).unwrap();
+        }
+        fs::set_permissions(&linked_path, fs::Permissions::from_mode(0o4755)).unwrap();
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        // Others only get a warning
+        resolve_all_with(
+            &mut io::sink(),
+            [symlink_path.clone()],
+            &Options {
+                preserve_owner: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        let copied_path = symlink_path.join("linked_file");
+        assert!(copied_path.is_file());
+        if is_root {
+            let owner = |path: &Path| {
+                let metadata = path.symlink_metadata().unwrap();
+                (metadata.uid(), metadata.gid())
+            };
+            assert_eq!(owner(&symlink_path), (1234, 5678));
+            assert_eq!(owner(&copied_path), (4321, 8765));
+            assert_eq!(
+                copied_path.metadata().unwrap().permissions().mode() & 0o7777,
+                0o4755
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mine_only() {
+        // SAFETY: Only reads the effective user of this process.
+        let is_root = unsafe { libc::geteuid() } == 0;
+
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let mine = dir.join("mine");
+        let theirs = dir.join("theirs");
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink(&linked_path, &mine);
+        let _symlink = symlink(&linked_path, &theirs);
+        // Only root can give away a link
+        if is_root {
+            std::os::unix::fs::lchown(&theirs, Some(1234), None).unwrap();
+        }
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [mine.clone(), theirs.clone()],
+            &Options {
+                mine_only: true,
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        let output = str::from_utf8(&buffer).unwrap();
+        assert!(output.starts_with(&format!("COPY {linked_path:?} => {mine:?}\n")));
+        assert!(!mine.is_symlink());
+        if is_root {
+            assert!(output.ends_with(&format!("SKIP NOT OWNER {theirs:?}\n")));
+            assert!(theirs.is_symlink());
+        }
+    }
+
+    #[test]
+    fn require_concrete() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let first = dir.join("first");
+        let second = dir.join("second");
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink(&second, &first);
+        let _symlink = symlink(&linked_path, &second);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [first.clone(), second.clone()],
+            &Options {
+                require_concrete: true,
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!("SKIP INDIRECT {first:?}\nCOPY {linked_path:?} => {second:?}\n")
+        );
+        assert_eq!(read_link(&first).unwrap(), second);
+        assert!(second.is_file() && !second.is_symlink());
+    }
+
+    #[test]
+    fn callback_actions() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let linked_path = subdir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        let broken_path = dir.join("broken");
+        create_dir(&subdir).unwrap();
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink(&subdir, &symlink_path);
+        let _symlink = symlink(dir.join("missing"), &broken_path);
+
+        let mut actions = Vec::new();
+        let summary = resolve_all_with_callback(
+            [symlink_path.clone(), dir.join("missing")],
+            &Options::default(),
+            |action| actions.push((action.verb(), action.path().to_path_buf())),
+        )
+        .unwrap();
+        assert_eq!(
+            actions,
+            [
+                ("POPULATE", symlink_path.clone()),
+                ("COPY", symlink_path.join("linked_file")),
+                ("SKIP INPUT", dir.join("missing")),
+            ]
+        );
+        assert_eq!((summary.copies, summary.populated), (1, 1));
+
+        // The adapter logs the same lines as writing to the output directly
+        let (mut logged, mut written) = (Vec::new(), Vec::new());
+        resolve_all_with_callback(
+            [broken_path.clone()],
+            &Options::default(),
+            text_logger(&mut logged),
+        )
+        .unwrap();
+        resolve_all_with(
+            &mut written,
+            [broken_path.clone()],
+            &Options {
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&logged).unwrap(),
+            format!("SKIP DANGLING {broken_path:?}\n")
+        );
+        assert_eq!(logged, written);
+    }
+
+    #[test]
+    fn case_collision() {
+        let dir = Temp::new_dir().unwrap();
+
+        let source = dir.join("source");
+        let staging = dir.join("staging");
+        let symlink_path = dir.join("symlink");
+        create_dir(&source).unwrap();
+        create_dir(&staging).unwrap();
+        fs::write(source.join("file"), "later").unwrap();
+        // As if "File" was populated first on a case-insensitive filesystem
+        fs::write(staging.join("file"), "earlier").unwrap();
+
+        let mut buffer = Vec::new();
+        let options = Options::default();
+        let mut ctx = Context::new(&mut buffer, &options);
+        ctx.staging = Some((staging.clone(), symlink_path.clone()));
+        ctx.run(vec![Work::Relink {
+            path: symlink_path.join("file"),
+            symlink_dest: Some(source.join("file")),
+            depth: 1,
+        }])
+        .unwrap();
+        assert_eq!(ctx.summary.skipped, 1);
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!("SKIP CASE COLLISION {:?}\n", symlink_path.join("file"))
+        );
+        assert_eq!(fs::read_to_string(staging.join("file")).unwrap(), "earlier");
+    }
+
+    #[test]
+    fn exclude_hidden() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let symlink_path = dir.join("symlink");
+        create_dir_all(subdir.join(".hidden_dir")).unwrap();
+        File::create(subdir.join(".hidden")).unwrap();
+        File::create(subdir.join(".hidden_dir").join("file")).unwrap();
+        File::create(subdir.join("visible")).unwrap();
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone()],
+            &Options {
+                exclude_hidden: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        let output = str::from_utf8(&buffer).unwrap();
+        assert!(output.contains(&format!("SKIP HIDDEN {:?}\n", symlink_path.join(".hidden"))));
+        assert!(output.contains(&format!(
+            "SKIP HIDDEN {:?}\n",
+            symlink_path.join(".hidden_dir")
+        )));
+        assert!(output.contains(" populated=1 skipped=2 "), "{output}");
+        assert!(symlink_path.join("visible").is_file());
+        assert!(!symlink_path.join(".hidden").exists());
+        assert!(!symlink_path.join(".hidden_dir").exists());
+    }
+
+    #[test]
+    fn count_actions_estimate() {
+        let dir = Temp::new_dir().unwrap();
+
+        let tree = dir.join("tree");
+        let source = dir.join("source");
+        create_dir_all(tree.join("real_dir")).unwrap();
+        create_dir_all(source.join("nested")).unwrap();
+        File::create(tree.join("real_file")).unwrap();
+        File::create(source.join("a")).unwrap();
+        File::create(source.join("b")).unwrap();
+        File::create(source.join("nested").join("c")).unwrap();
+        let _symlink = symlink(source.join("a"), tree.join("file_link"));
+        let _symlink = symlink(&source, tree.join("real_dir").join("dir_link"));
+        let _symlink = symlink(dir.join("missing"), tree.join("broken"));
+
+        let inputs = [tree.clone(), dir.join("missing")];
+        let total = count_actions(&inputs);
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            inputs,
+            &Options {
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        // Three links, four populated entries, and a missing input
+        assert_eq!(total, 8);
+        assert_eq!(str::from_utf8(&buffer).unwrap().lines().count(), total);
+    }
+
+    #[test]
+    fn retry_transient() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static FAILURES: AtomicUsize = AtomicUsize::new(0);
+
+        fn flaky_copy(src: &Path, dest: &Path) -> io::Result<u64> {
+            if FAILURES.fetch_add(1, Ordering::Relaxed) < 2 {
+                Err(io::Error::from(ErrorKind::Interrupted))
+            } else {
+                copy_file(src, dest)
+            }
+        }
+
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+
+        for (retry, resolved) in [(1, false), (2, true)] {
+            FAILURES.store(0, Ordering::Relaxed);
+            let mut buffer = Vec::new();
+            let options = Options {
+                no_summary: true,
+                retry,
+                ..Options::default()
+            };
+            let mut ctx = Context::new(&mut buffer, &options);
+            ctx.copy = flaky_copy;
+            ctx.exec([symlink_path.clone()]).unwrap();
+
+            assert_eq!(ctx.summary.errors, usize::from(!resolved));
+            assert_eq!(symlink_path.is_symlink(), !resolved);
+        }
+    }
+
+    #[test]
+    fn dir_link_input_forms() {
+        let suffixes = ["", path::MAIN_SEPARATOR_STR, "//", "/.", "/./"];
+        for suffix in suffixes {
+            let dir = Temp::new_dir().unwrap();
+
+            let subdir = dir.join("real_dir");
+            let symlink_path = dir.join("symlink");
+            create_dir(&subdir).unwrap();
+            File::create(subdir.join("file")).unwrap();
+            let _symlink = symlink(&subdir, &symlink_path);
+
+            let mut input = symlink_path.clone().into_os_string();
+            input.push(suffix);
+
+            let mut buffer = Vec::new();
+            resolve_all_with(
+                &mut buffer,
+                [PathBuf::from(input)],
+                &Options {
+                    no_summary: true,
+                    ..Options::default()
+                },
+            )
+            .unwrap();
+            assert_eq!(
+                str::from_utf8(&buffer).unwrap(),
+                format!(
+                    "POPULATE {subdir:?} => {symlink_path:?}\nCOPY {:?} => {:?}\n",
+                    subdir.join("file"),
+                    symlink_path.join("file")
+                ),
+                "{suffix:?}"
+            );
+            assert!(symlink_path.is_dir() && !symlink_path.is_symlink());
+        }
+    }
+
+    #[test]
+    fn manifest() {
+        let dir = Temp::new_dir().unwrap();
+        let manifest_path = Temp::new_file().unwrap();
+        let dir_path = canonicalize(&dir).unwrap();
+
+        let subdir = dir_path.join("real_dir");
+        let sub_subdir = subdir.join("inner");
+        let symlink_path = dir_path.join("symlink");
+        create_dir_all(&sub_subdir).unwrap();
+        File::create(sub_subdir.join("linked_file")).unwrap();
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone()],
+            &Options {
+                quiet: true,
+                manifest: Some(manifest_path.to_path_buf()),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+
+        let inner = Path::new("inner");
+        let file = inner.join("linked_file");
+        assert_eq!(
+            fs::read_to_string(&manifest_path).unwrap(),
+            format!(
+                "{}\t{}\n{}\t{}\n{}\t{}\n",
+                symlink_path.display(),
+                subdir.display(),
+                symlink_path.join(inner).display(),
+                sub_subdir.display(),
+                symlink_path.join(&file).display(),
+                subdir.join(&file).display(),
+            )
+        );
+    }
+
+    #[test]
+    fn newer_than() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let old_link = dir.join("old_link");
+        let new_link = dir.join("new_link");
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink(&linked_path, &old_link);
+        let _symlink = symlink(&linked_path, &new_link);
+
+        // Backdate one link by a day
+        let cutoff = SystemTime::now() - Duration::from_secs(60);
+        let old = cutoff - Duration::from_secs(60 * 60 * 24);
+        let backdated = dir.join("backdated");
+        File::create(&backdated)
+            .unwrap()
+            .set_times(FileTimes::new().set_accessed(old).set_modified(old))
+            .unwrap();
+        set_link_times(&old_link, &backdated.metadata().unwrap()).unwrap();
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [old_link.clone(), new_link.clone()],
+            &Options {
+                newer_than: Some(cutoff),
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!("SKIP OLD {old_link:?}\nCOPY {linked_path:?} => {new_link:?}\n")
+        );
+        assert!(old_link.is_symlink());
+        assert!(!new_link.is_symlink());
+    }
+
+    #[test]
+    fn into() {
+        let dir = Temp::new_dir().unwrap();
+
+        let tree = dir.join("tree");
+        let source = dir.join("source");
+        let into = dir.join("out");
+        create_dir_all(tree.join("real_dir")).unwrap();
+        create_dir(&source).unwrap();
+        File::create(tree.join("real_dir").join("real_file")).unwrap();
+        File::create(source.join("linked_file")).unwrap();
+        let file_link = tree.join("real_dir").join("file_link");
+        let dir_link = tree.join("dir_link");
+        let _symlink = symlink(source.join("linked_file"), &file_link);
+        let _symlink = symlink(&source, &dir_link);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [tree.clone()],
+            &Options {
+                into: Some(into.clone()),
+                quiet: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+
+        let mirrored = into.join("tree");
+        assert!(mirrored.join("real_dir").join("real_file").is_file());
+        for link in [&file_link, &dir_link] {
+            let copy = mirrored.join(link.strip_prefix(&tree).unwrap());
+            assert!(copy.exists() && !copy.is_symlink(), "{copy:?}");
+        }
+        assert!(mirrored.join("dir_link").join("linked_file").is_file());
+
+        // The original tree is left alone
+        assert_eq!(read_link(&file_link).unwrap(), source.join("linked_file"));
+        assert_eq!(read_link(&dir_link).unwrap(), source);
+        assert_eq!(read_dir(&dir).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn regular_file_input() {
+        let dir = Temp::new_dir().unwrap();
+
+        let file_path = dir.join("file");
+        let subdir = dir.join("real_dir");
+        create_dir(&subdir).unwrap();
+        File::create(&file_path).unwrap();
+        File::create(subdir.join("inner_file")).unwrap();
+
+        let mut buffer = Vec::new();
+        let summary = resolve_all_with(
+            &mut buffer,
+            [file_path.clone(), subdir.clone()],
+            &Options {
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        // Files inside of an input directory are still passed over quietly
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!("SKIP NOT A LINK {file_path:?}\n")
+        );
+        assert_eq!(summary.skipped, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn preserve_exec() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        File::create(&linked_path).unwrap();
+        fs::set_permissions(&linked_path, fs::Permissions::from_mode(0o750)).unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+
+        resolve_all_with(
+            &mut io::sink(),
+            [symlink_path.clone()],
+            &Options {
+                preserve_exec: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        let mode = symlink_path
+            .symlink_metadata()
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o7777;
+        assert_eq!(mode, (0o666 & !umask()) | 0o110, "{mode:o}");
+    }
+
+    #[test]
+    fn glob_inputs() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let first_link = dir.join("link_a");
+        let second_link = dir.join("link_b");
+        let other_link = dir.join("other");
+        File::create(&linked_path).unwrap();
+        for link in [&first_link, &second_link, &other_link] {
+            let _symlink = symlink(&linked_path, link);
+        }
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [dir.join("link_*")],
+            &Options {
+                glob: true,
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "COPY {linked_path:?} => {first_link:?}\nCOPY {linked_path:?} => {second_link:?}\n"
+            )
+        );
+        assert!(other_link.is_symlink());
+    }
+
+    #[test]
+    fn copy_error_context() {
+        fn failing_copy(_src: &Path, _dest: &Path) -> io::Result<u64> {
+            Err(io::Error::from(ErrorKind::StorageFull))
+        }
+
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+
+        let options = Options {
+            on_error: OnError::Abort,
+            ..Options::default()
+        };
+        let mut buffer = Vec::new();
+        let mut ctx = Context::new(&mut buffer, &options);
+        ctx.copy = failing_copy;
+        let e = ctx.exec([symlink_path.clone()]).unwrap_err();
+        // Still usable as the original error
+        assert_eq!(e.kind(), ErrorKind::StorageFull);
+
+        let Some(DelinkError::Copy { src, dest, source }) = DelinkError::from_io(&e) else {
+            panic!("{e:?}");
+        };
+        assert_eq!(src, &linked_path);
+        assert_eq!(dest, &symlink_path);
+        assert_eq!(source.kind(), ErrorKind::StorageFull);
+    }
+
+    #[test]
+    fn show_chain() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let first = dir.join("first");
+        let second = dir.join("second");
+        let third = dir.join("third");
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink("linked_file", &third);
+        let _symlink = symlink("second", &first);
+        let _symlink = symlink(&third, &second);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [first.clone()],
+            &Options {
+                show_chain: true,
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "CHAIN {first:?} -> {second:?} -> {third:?} -> {linked_path:?}\nCOPY {:?} => {first:?}\n",
+                canonicalize(&linked_path).unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn interleaved_stdin() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let first_link = dir.join("first");
+        let second_link = dir.join("second");
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink(&linked_path, &first_link);
+        let _symlink = symlink(&linked_path, &second_link);
+
+        // Stdin repeats the argument before it
+        let mut stdin = second_link.clone().into_os_string();
+        stdin.push("\n");
+        stdin.push(&first_link);
+        stdin.push("\n");
+        let args = [first_link.clone(), PathBuf::from("-")];
+        let inputs: Vec<_> = input_paths(args, stdin.as_encoded_bytes(), b'\n')
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            inputs,
+            [first_link.clone(), second_link.clone(), first_link.clone()]
+        );
+
+        let mut buffer = Vec::new();
+        let summary = resolve_all_with(
+            &mut buffer,
+            inputs,
+            &Options {
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "COPY {linked_path:?} => {first_link:?}\nCOPY {linked_path:?} => {second_link:?}\nSKIP CONTAINED {first_link:?}\n"
+            )
+        );
+        assert_eq!((summary.copies, summary.skipped), (2, 1));
+    }
+
+    #[test]
+    fn dirs_only() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let subdir = dir.join("real_dir");
+        let file_link = dir.join("file_link");
+        let dir_link = dir.join("dir_link");
+        create_dir(&subdir).unwrap();
+        File::create(&linked_path).unwrap();
+        File::create(subdir.join("inner_file")).unwrap();
+        let _symlink = symlink(&linked_path, &file_link);
+        let _symlink = symlink(&subdir, &dir_link);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [file_link.clone(), dir_link.clone()],
+            &Options {
+                dirs_only: true,
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "SKIP FILE LINK {file_link:?}\nPOPULATE {subdir:?} => {dir_link:?}\nCOPY {:?} => {:?}\n",
+                subdir.join("inner_file"),
+                dir_link.join("inner_file")
+            )
+        );
+        assert!(file_link.is_symlink());
+        assert!(dir_link.is_dir() && !dir_link.is_symlink());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn target_root() {
+        let dir = Temp::new_dir().unwrap();
+        let root = dir.canonicalize().unwrap();
+
+        // Only exists under the remapped root
+        let linked_path = root.join("delink_target_root").join("file");
+        create_dir(root.join("delink_target_root")).unwrap();
+        File::create(&linked_path).unwrap();
+        let link = root.join("link");
+        let _symlink = symlink("/delink_target_root/file", &link);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [link.clone()],
+            &Options {
+                target_root: Some(root.clone()),
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!("COPY {linked_path:?} => {link:?}\n")
+        );
+        assert!(link.is_file() && !link.is_symlink());
+    }
+
+    #[test]
+    fn error_format_json() {
+        let dir = Temp::new_dir().unwrap();
+        let stderr = Temp::new_file().unwrap();
+
+        let bad_path = dir.join("fail");
+        let bad_link = dir.join("bad_link");
+        File::create(&bad_path).unwrap();
+        let _symlink = symlink(&bad_path, &bad_link);
+
+        let mut buffer = Vec::new();
+        let options = Options {
+            no_summary: true,
+            error_format: Format::Json,
+            ..Options::default()
+        };
+        let mut ctx = Context::new(&mut buffer, &options);
+        ctx.copy = failing_copy;
+        ctx.errors = Box::new(File::create(&stderr).unwrap());
+        ctx.exec([bad_link.clone()]).unwrap();
+        drop(ctx);
+
+        let errors = std::fs::read_to_string(&stderr).unwrap();
+        let lines: Vec<_> = errors.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let error: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(error["path"], bad_link.to_str().unwrap());
+        assert!(
+            error["message"]
+                .as_str()
+                .unwrap()
+                .ends_with("injected failure")
+        );
+    }
+
+    #[test]
+    fn warning_format_json() {
+        let dir = Temp::new_dir().unwrap();
+        let stderr = Temp::new_file().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let links = [dir.join("a"), dir.join("b")];
+        File::create(&linked_path).unwrap();
+        for link in &links {
+            let _symlink = symlink(&linked_path, link);
+        }
+
+        let mut buffer = Vec::new();
+        let options = Options {
+            move_files: true,
+            no_summary: true,
+            error_format: Format::Json,
+            ..Options::default()
+        };
+        let mut ctx = Context::new(&mut buffer, &options);
+        ctx.errors = Box::new(File::create(&stderr).unwrap());
+        ctx.exec(links).unwrap();
+        drop(ctx);
+
+        // Both links share the file, so neither moves it
+        let warnings = fs::read_to_string(&stderr).unwrap();
+        let lines: Vec<_> = warnings.lines().collect();
+        assert_eq!(lines.len(), 2);
+        for line in lines {
+            let warning: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(warning["level"], "warning");
+            assert_eq!(warning["path"], linked_path.to_str().unwrap());
+            assert!(warning["message"].is_string());
+        }
+    }
+
+    #[test]
+    fn limit() {
+        let dir = Temp::new_dir().unwrap();
+
+        let links: Vec<_> = (0..3).map(|idx| dir.join(format!("link_{idx}"))).collect();
+        for (idx, link) in links.iter().enumerate() {
+            let linked_path = dir.join(format!("file_{idx}"));
+            File::create(&linked_path).unwrap();
+            let _symlink = symlink(&linked_path, link);
+        }
+
+        let mut buffer = Vec::new();
+        let summary = resolve_all_with(
+            &mut buffer,
+            links.clone(),
+            &Options {
+                limit: Some(1),
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "COPY {:?} => {:?}\nSTOP LIMIT REACHED {:?}\n",
+                dir.join("file_0"),
+                links[0],
+                links[1]
+            )
+        );
+        assert_eq!(summary.copies, 1);
+        assert!(!links[0].is_symlink());
+        assert!(links[1].is_symlink() && links[2].is_symlink());
+    }
+
+    #[test]
+    fn relativize_broken() {
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.canonicalize().unwrap();
+
+        let link_dir = dir.join("links");
+        let walked_link = link_dir.join("walked");
+        let input_link = dir.join("input");
+        create_dir(&link_dir).unwrap();
+        let _symlink = symlink(dir.join("missing"), &walked_link);
+        let _symlink = symlink(dir.join("links").join("missing"), &input_link);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [link_dir.clone(), input_link.clone()],
+            &Options {
+                no_summary: true,
+                relativize: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        let walked_relative = Path::new("..").join("missing");
+        let input_relative = Path::new("links").join("missing");
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "RELATIVIZE {walked_link:?} => {walked_relative:?}\n\
+                 RELATIVIZE {input_link:?} => {input_relative:?}\n"
+            )
+        );
+        assert_eq!(read_link(&walked_link).unwrap(), walked_relative);
+        assert_eq!(read_link(&input_link).unwrap(), input_relative);
+    }
+
+    #[test]
+    fn timing() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        fs::write(&linked_path, "contents").unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone()],
+            &Options {
+                timing: true,
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        let output = str::from_utf8(&buffer).unwrap();
+        let prefix = format!("COPY {linked_path:?} => {symlink_path:?} (8 B in ");
+        assert!(output.starts_with(&prefix), "{output}");
+        assert!(output.ends_with("/s)\n"), "{output}");
+        assert!(symlink_path.is_file() && !symlink_path.is_symlink());
+    }
+
+    #[test]
+    fn time() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [symlink_path.clone()],
+            &Options {
+                time: true,
+                quiet: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        // Only the line, since the duration differs between runs
+        let output = str::from_utf8(&buffer).unwrap();
+        assert!(output.starts_with("ELAPSED "), "{output}");
+        assert_eq!(output.lines().count(), 1, "{output}");
+        assert!(symlink_path.is_file() && !symlink_path.is_symlink());
+
+        let mut buffer = Vec::new();
+        resolve_all(&mut buffer, [dir.to_path_buf()]).unwrap();
+        assert!(!str::from_utf8(&buffer).unwrap().contains("ELAPSED"));
+    }
+
+    #[test]
+    fn color() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        let broken_path = dir.join("broken");
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink(&linked_path, &symlink_path);
+        let _symlink = symlink(dir.join("missing"), &broken_path);
+
+        let resolve = |color| {
+            let mut buffer = Vec::new();
+            resolve_all_with(
+                &mut buffer,
+                [symlink_path.clone(), broken_path.clone()],
+                &Options {
+                    color,
+                    no_summary: true,
+                    dry_run: true,
+                    ..Options::default()
+                },
+            )
+            .unwrap();
+            String::from_utf8(buffer).unwrap()
+        };
+
+        // Plain when not asked for, as with any output that is not a terminal
+        let plain = resolve(false);
+        assert!(!plain.contains('\x1b'), "{plain:?}");
+        assert_eq!(
+            resolve(true),
+            format!(
+                "\x1b[32mCOPY\x1b[0m {linked_path:?} => {symlink_path:?}\n\
+                 \x1b[33mSKIP DANGLING\x1b[0m {broken_path:?}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn merge() {
+        let dir = Temp::new_dir().unwrap();
+
+        let tree = dir.join("tree");
+        let source = dir.join("source");
+        let into = dir.join("out");
+        let mirrored = into.join("tree");
+        create_dir_all(tree.join("real_dir")).unwrap();
+        create_dir(&source).unwrap();
+        fs::write(tree.join("real_dir").join("real_file"), "new").unwrap();
+        File::create(source.join("linked_file")).unwrap();
+        let _symlink = symlink(&source, tree.join("dir_link"));
+
+        // Left by an earlier run
+        create_dir_all(mirrored.join("real_dir")).unwrap();
+        fs::write(mirrored.join("real_dir").join("real_file"), "old").unwrap();
+        File::create(mirrored.join("real_dir").join("extra_file")).unwrap();
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [tree.clone()],
+            &Options {
+                into: Some(into.clone()),
+                merge: true,
+                backup: Some(".bak".into()),
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+
+        let replaced = mirrored.join("real_dir").join("real_file");
+        let backup = mirrored.join("real_dir").join("real_file.bak");
+        assert!(
+            str::from_utf8(&buffer)
+                .unwrap()
+                .ends_with(&format!("BACKUP {replaced:?} => {backup:?}\n"))
+        );
+        assert_eq!(fs::read_to_string(&replaced).unwrap(), "new");
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "old");
+        assert!(mirrored.join("real_dir").join("extra_file").is_file());
+        assert!(mirrored.join("dir_link").join("linked_file").is_file());
+        assert_eq!(read_dir(&into).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn no_clobber() {
+        let dir = Temp::new_dir().unwrap();
+
+        let tree = dir.join("tree");
+        let into = dir.join("out");
+        let mirrored = into.join("tree");
+        create_dir(&tree).unwrap();
+        fs::write(tree.join("existing_file"), "new").unwrap();
+        fs::write(tree.join("new_file"), "new").unwrap();
+
+        // Left by an earlier run
+        create_dir(&into).unwrap();
+        create_dir(&mirrored).unwrap();
+        fs::write(mirrored.join("existing_file"), "old").unwrap();
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            [tree.clone()],
+            &Options {
+                into: Some(into.clone()),
+                merge: true,
+                no_clobber: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+
+        let output = str::from_utf8(&buffer).unwrap();
+        let existing = mirrored.join("existing_file");
+        assert!(output.contains(&format!("SKIP EXISTS {existing:?}\n")));
+        assert!(output.contains(&format!(
+            "COPY {:?} => {:?}\n",
+            tree.join("new_file"),
+            mirrored.join("new_file")
+        )));
+        assert!(output.contains(" skipped=1 "));
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "old");
+        assert_eq!(
+            fs::read_to_string(mirrored.join("new_file")).unwrap(),
+            "new"
+        );
+        assert_eq!(read_dir(&mirrored).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn response_file() {
+        let dir = Temp::new_dir().unwrap();
+
+        let linked_path = dir.join("linked_file");
+        let first_link = dir.join("first");
+        let second_link = dir.join("second");
+        let response_file = dir.join("paths.txt");
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink(&linked_path, &first_link);
+        let _symlink = symlink(&linked_path, &second_link);
+
+        let mut contents = first_link.clone().into_os_string();
+        contents.push("\n");
+        contents.push(&second_link);
+        contents.push("\n");
+        fs::write(&response_file, contents.as_encoded_bytes()).unwrap();
+
+        let mut arg = OsString::from("@");
+        arg.push(&response_file);
+        let inputs = expand_response_files([PathBuf::from(arg)]).unwrap();
+        assert_eq!(inputs, [first_link.clone(), second_link.clone()]);
+
+        let mut buffer = Vec::new();
+        resolve_all_with(
+            &mut buffer,
+            inputs,
+            &Options {
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!(
+                "COPY {linked_path:?} => {first_link:?}\nCOPY {linked_path:?} => {second_link:?}\n"
+            )
+        );
+
+        let missing = expand_response_files(["@missing"]).unwrap_err();
+        assert_eq!(
+            DelinkError::from_io(&missing).unwrap().path(),
+            Path::new("missing")
+        );
+    }
+
+    #[test]
+    fn distinct_targets() {
+        let dir = Temp::new_dir().unwrap();
+
+        let first_path = dir.join("first_file");
+        let second_path = dir.join("second_file");
+        File::create(&first_path).unwrap();
+        File::create(&second_path).unwrap();
+        let links = [dir.join("a"), dir.join("b"), dir.join("c")];
+        let _symlink = symlink(&first_path, &links[0]);
+        let _symlink = symlink(&first_path, &links[1]);
+        let _symlink = symlink(&second_path, &links[2]);
+
+        let mut buffer = Vec::new();
+        let summary = resolve_all_with(&mut buffer, links, &Options::default()).unwrap();
+        assert_eq!((summary.copies, summary.distinct_targets), (3, 2));
+        assert!(
+            str::from_utf8(&buffer)
+                .unwrap()
+                .ends_with(" distinct_targets=2\n")
+        );
+    }
+
+    #[test]
+    fn distinct_targets_populated() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let linked_path = subdir.join("linked_file");
+        let symlink_path = dir.join("symlink");
+        create_dir(&subdir).unwrap();
+        File::create(&linked_path).unwrap();
+        let _symlink = symlink(&linked_path, subdir.join("file_link"));
+        let _symlink = symlink(&subdir, &symlink_path);
+
+        let mut buffer = Vec::new();
+        let summary = resolve_all_with(&mut buffer, [symlink_path], &Options::default()).unwrap();
+        // The directory, and the file reached both directly and by its link
+        assert_eq!((summary.copies, summary.distinct_targets), (2, 2));
+    }
+
+    #[test]
+    fn skip_larger_than() {
+        let dir = Temp::new_dir().unwrap();
+
+        let small_path = dir.join("small");
+        let large_path = dir.join("large");
+        let small_link = dir.join("small_link");
+        let large_link = dir.join("large_link");
+        fs::write(&small_path, [0; 10]).unwrap();
+        fs::write(&large_path, [0; 2048]).unwrap();
+        let _symlink = symlink(&small_path, &small_link);
+        let _symlink = symlink(&large_path, &large_link);
+
+        let mut buffer = Vec::new();
+        let summary = resolve_all_with(
+            &mut buffer,
+            [small_link.clone(), large_link.clone()],
+            &Options {
+                skip_larger_than: Some(1024),
+                no_summary: true,
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            str::from_utf8(&buffer).unwrap(),
+            format!("COPY {small_path:?} => {small_link:?}\nSKIP LARGE {large_link:?}\n")
+        );
+        assert_eq!((summary.copies, summary.skipped), (1, 1));
+        assert!(small_link.is_file() && !small_link.is_symlink());
+        assert_eq!(read_link(&large_link).unwrap(), large_path);
+    }
+
+    #[test]
+    fn flatten() {
+        let dir = Temp::new_dir().unwrap();
+
+        let subdir = dir.join("real_dir");
+        let nested = subdir.join("nested");
+        create_dir_all(nested.join("deeper")).unwrap();
+        fs::write(subdir.join("file.txt"), "top").unwrap();
+        fs::write(nested.join("file.txt"), "nested").unwrap();
+        fs::write(nested.join("deeper").join("other"), "deeper").unwrap();
+
+        // Whichever file.txt comes first in directory order keeps the name
+        for (collision, names, copies) in [
+            (
+                Collision::Rename,
+                ["file-1.txt", "file.txt", "other"].as_slice(),
+                3,
+            ),
+            (Collision::Skip, ["file.txt", "other"].as_slice(), 2),
+        ] {
+            let symlink_path = dir.join("symlink");
+            let _symlink = symlink(&subdir, &symlink_path);
+
+            let summary = resolve_all_with(
+                &mut io::sink(),
+                [symlink_path.clone()],
+                &Options {
+                    flatten: Some(collision),
+                    ..Options::default()
+                },
+            )
+            .unwrap();
+            assert_eq!(summary.copies, copies);
+            assert_eq!(summary.skipped, 3 - copies);
+
+            let mut entries: Vec<_> = read_dir(&symlink_path)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .collect();
+            entries.sort();
+            assert!(entries.iter().all(|path| path.is_file()), "{entries:?}");
+            let names: Vec<_> = names.iter().map(|name| symlink_path.join(name)).collect();
+            assert_eq!(entries, names);
+            if collision == Collision::Rename {
+                let mut contents: Vec<_> = entries
+                    .iter()
+                    .map(|path| fs::read_to_string(path).unwrap())
+                    .collect();
+                contents.sort();
+                assert_eq!(contents, ["deeper", "nested", "top"]);
+            }
+
+            fs::remove_dir_all(&symlink_path).unwrap();
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn path_too_long() {
+        // Past PATH_MAX once nested, though every link is in a short path
+        const LEVELS: usize = 20;
+        let name = "n".repeat(240);
+
+        let dir = Temp::new_dir().unwrap();
+        let dir = dir.canonicalize().unwrap();
+
+        let source = dir.join("source");
+        for level in 0..LEVELS {
+            let level_dir = source.join(level.to_string());
+            create_dir_all(&level_dir).unwrap();
+            File::create(level_dir.join("file")).unwrap();
+            if level + 1 < LEVELS {
+                let _symlink = symlink(source.join((level + 1).to_string()), level_dir.join(&name));
+            }
+        }
+        let symlink_path = dir.join("symlink");
+        let _symlink = symlink(source.join("0"), &symlink_path);
+
+        let mut buffer = Vec::new();
+        let summary =
+            resolve_all_with(&mut buffer, [symlink_path.clone()], &Options::default()).unwrap();
+        let output = str::from_utf8(&buffer).unwrap();
+        assert_eq!(summary.errors, 0, "{output}");
+        assert!(output.contains("SKIP PATH TOO LONG "), "{output}");
+
+        // Everything that fit was still populated
+        assert!(!symlink_path.is_symlink());
+        assert!(symlink_path.join("file").is_file());
+        assert!(symlink_path.join(&name).join("file").is_file());
+    }
+}
@@ -5,304 +5,582 @@
  */
 
 use std::{
-    env::args,
-    fs::{self, read_dir, read_link, remove_file},
-    io::{self, BufRead, BufReader, ErrorKind, Write},
+    env::{self, args},
+    ffi::OsString,
+    fs::File,
+    io::{self, BufReader, BufWriter, ErrorKind, IsTerminal, Write},
     iter,
-    path::{self, Path, PathBuf},
+    path::PathBuf,
+    process,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-fn resolve_symlink<W: Write>(path: &PathBuf, stdout: &mut W) -> io::Result<Option<PathBuf>> {
-    fn inner_resolve_symlink(path: &PathBuf) -> io::Result<Option<PathBuf>> {
-        if path.symlink_metadata()?.is_symlink() {
-            let entry_dest = fs::read_link(path)?;
-            Ok(Some(
-                if entry_dest.is_relative()
-                    && let Some(parent) = path.parent()
-                {
-                    parent.join(entry_dest).canonicalize()?
-                } else {
-                    entry_dest.canonicalize()?
-                },
-            ))
-        } else {
-            Ok(None)
-        }
-    }
+use delink::{
+    Collision, DelinkError, Format, OnError, Options, Reflink, Summary, expand_response_files,
+    input_paths, path_from_url, resolve_all_with_summary, undo, write_error,
+};
 
-    match inner_resolve_symlink(path) {
-        Ok(x) => Ok(x),
-        // Ignore FilesystemLoop errors caused by self-referential symbolic
-        // links.
-        Err(e) if e.raw_os_error() == Some(40) => {
-            let _ = writeln!(stdout, "SKIP SELF REFERENCE {path:?}");
-            Ok(None)
-        }
-        Err(e) => Err(e),
-    }
+fn invalid_input(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, msg)
 }
 
-fn maybe_remove_file<P: AsRef<Path>>(path: P) -> io::Result<()> {
-    match remove_file(path) {
-        Ok(()) => Ok(()),
-        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
-        Err(e) => Err(e),
-    }
+/// Takes the argument following `flag`.
+fn flag_value<I: Iterator<Item = String>>(args: &mut I, flag: &str) -> io::Result<String> {
+    args.next()
+        .ok_or_else(|| invalid_input(format!("{flag} requires a value")))
 }
 
-fn relink<W: Write>(
-    // Absolute but not canonical path
-    path: &mut PathBuf,
-    // Canonical path
-    symlink_dest: Option<PathBuf>,
-    stdout: &mut W,
-) -> io::Result<()> {
-    // Only two relevant entries are directories and symlinks.
-    if let Some(dest) = symlink_dest {
-        if dest == *path {
-            let _ = writeln!(stdout, "SKIP SELF REFERENCE {dest:?}");
-        } else if dest.is_file() {
-            let _ = writeln!(stdout, "COPY {dest:?} => {path:?}");
-            maybe_remove_file(&path)?;
-            fs::copy(dest, path)?;
-        } else {
-            debug_assert!(dest.is_dir(), "{dest:?} NOT dir");
+/// Takes and parses the argument following `flag`.
+fn parse_flag_value<I, T>(args: &mut I, flag: &str) -> io::Result<T>
+where
+    I: Iterator<Item = String>,
+    T: FromStr,
+{
+    flag_value(args, flag)?
+        .trim()
+        .parse()
+        .map_err(|_| invalid_input(format!("Invalid value for {flag}")))
+}
 
-            if path.starts_with(&dest) {
-                let _ = writeln!(stdout, "SKIP RECURSIVE {path:?}");
-            } else {
-                let _ = writeln!(stdout, "POPULATE {dest:?} => {path:?}");
-                maybe_remove_file(&path)?;
-                fs::create_dir(&path)?;
+/// Single byte to split stdin paths on, given to `flag`.
+///
+/// Not trimmed, so whitespace can be used.
+fn delimiter_value<I: Iterator<Item = String>>(args: &mut I, flag: &str) -> io::Result<u8> {
+    match flag_value(args, flag)?.as_bytes() {
+        &[byte] => Ok(byte),
+        _ => Err(invalid_input(format!("{flag} must be a single byte"))),
+    }
+}
 
-                for sub_dest in read_dir(dest)? {
-                    let sub_dest = sub_dest?.path();
-                    if let Some(sub_dest_file_name) = sub_dest.file_name() {
-                        // Reuse path object for the dest
-                        path.push(sub_dest_file_name);
-                        relink(path, Some(sub_dest), stdout)?;
-                        // Clean path object back up
-                        path.pop();
-                    }
-                }
-            }
-        }
-    } else if path.is_dir() {
-        for entry in read_dir(&path)? {
-            let entry = entry?;
-            let mut entry_path = entry.path();
-            let entry_dest = resolve_symlink(&entry_path, stdout)?;
+/// Time given as RFC 3339, or `@` followed by seconds since the Unix epoch.
+struct Timestamp(SystemTime);
+
+impl FromStr for Timestamp {
+    type Err = ();
 
-            relink(&mut entry_path, entry_dest, stdout)?;
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let (seconds, nanos) = match s.strip_prefix('@') {
+            Some(seconds) => (seconds.parse().map_err(drop)?, 0),
+            None => rfc3339(s).ok_or(())?,
+        };
+        let whole = Duration::from_secs(seconds.unsigned_abs());
+        if seconds < 0 {
+            UNIX_EPOCH.checked_sub(whole)
+        } else {
+            UNIX_EPOCH.checked_add(whole)
         }
-    } else {
-        debug_assert!(
-            path.is_file()
-                || (path.is_symlink() && read_link(path).and_then(|p| p.canonicalize()).is_err())
-        )
+        .and_then(|time| time.checked_add(Duration::from_nanos(nanos.into())))
+        .map(Self)
+        .ok_or(())
     }
-
-    Ok(())
 }
 
-fn exec<W, I>(stdout: &mut W, paths: I) -> io::Result<()>
-where
-    W: Write,
-    I: IntoIterator<Item = PathBuf>,
-{
-    for path in paths {
-        if path.try_exists().is_ok_and(|x| x) {
-            let symlink_dest = resolve_symlink(&path, stdout)?;
-            relink(&mut path::absolute(path)?, symlink_dest, stdout)?;
-        } else {
-            let _ = writeln!(stdout, "SKIP INPUT {path:?}");
+/// Seconds and nanoseconds since the Unix epoch of an RFC 3339 timestamp,
+/// such as `2024-01-02T03:04:05.6+07:00`.
+fn rfc3339(s: &str) -> Option<(i64, u32)> {
+    let number = |start: usize, len: usize| -> Option<i64> {
+        let digits = s.get(start..start + len)?;
+        digits
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+            .then(|| digits.parse().ok())?
+    };
+    let separators = s.as_bytes().get(..19)?;
+    if separators[4] != b'-'
+        || separators[7] != b'-'
+        || !matches!(separators[10], b'T' | b't' | b' ')
+        || separators[13] != b':'
+        || separators[16] != b':'
+    {
+        return None;
+    }
+    let (year, month, day) = (number(0, 4)?, number(5, 2)?, number(8, 2)?);
+    let (hour, minute, second) = (number(11, 2)?, number(14, 2)?, number(17, 2)?);
+
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let month_days = [
+        31,
+        28 + i64::from(leap),
+        31,
+        30,
+        31,
+        30,
+        31,
+        31,
+        30,
+        31,
+        30,
+        31,
+    ];
+    if !(1..=12).contains(&month)
+        || !(1..=month_days[month as usize - 1]).contains(&day)
+        || hour > 23
+        || minute > 59
+        // Leap seconds are allowed
+        || second > 60
+    {
+        return None;
+    }
+
+    let mut rest = &s[19..];
+    let mut nanos = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let len = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if len == 0 {
+            return None;
         }
+        // Digits past nanoseconds are dropped
+        let digits = &fraction[..len.min(9)];
+        nanos = digits.parse::<u32>().ok()? * 10_u32.pow(9 - digits.len() as u32);
+        rest = &fraction[len..];
     }
+    let offset = match rest.as_bytes() {
+        b"Z" | b"z" => 0,
+        [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+            let (hours, minutes) = (
+                rest[1..3].parse::<i64>().ok()?,
+                rest[4..].parse::<i64>().ok()?,
+            );
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            let offset = hours * 60 * 60 + minutes * 60;
+            if *sign == b'-' { -offset } else { offset }
+        }
+        _ => return None,
+    };
 
-    Ok(())
+    // Days since the epoch, counting years from March so leap days are last
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    Some((
+        days * 24 * 60 * 60 + hour * 60 * 60 + minute * 60 + second - offset,
+        nanos,
+    ))
 }
 
 fn main() -> io::Result<()> {
-    let mut args = args();
+    let mut args = args().peekable();
     let first_input = args
         .by_ref()
         .next()
         .expect("Always a first argument of executable name");
 
+    // Take all leading option flags
+    let mut options = Options::default();
+    let mut undo_journal = None;
+    let mut delimiter = b'\n';
+    let mut stats_json = false;
+    let mut output_path = None;
+    let mut no_color = false;
+    while let Some(flag) = args.next_if(|arg| {
+        (arg.starts_with("--") && arg.trim() != "--help") || ["-0", "-x"].contains(&arg.as_str())
+    }) {
+        match flag.trim() {
+            "--null" | "-0" => delimiter = b'\0',
+            "--delimiter" => delimiter = delimiter_value(&mut args, &flag)?,
+            "--dry-run" => options.dry_run = true,
+            "--hardlink" => options.hardlink = true,
+            "--dedup" => options.dedup = true,
+            "--move" => options.move_files = true,
+            "--preserve-timestamps" => options.preserve_timestamps = true,
+            "--preserve-link-times" => options.preserve_link_times = true,
+            "--preserve-dir-perms" => options.preserve_dir_perms = true,
+            "--flatten" | "--flatten=rename" => options.flatten = Some(Collision::Rename),
+            "--flatten=skip" => options.flatten = Some(Collision::Skip),
+            "--preserve-xattr" => options.preserve_xattr = true,
+            "--preserve-owner" => options.preserve_owner = true,
+            "--preserve-exec" => options.preserve_exec = true,
+            "--recreate-special" => options.recreate_special = true,
+            "--prune-broken" => options.prune_broken = true,
+            "--only-broken" => options.only_broken = true,
+            "--no-canonicalize" => options.no_canonicalize = true,
+            "--logical" => options.logical = true,
+            "--physical" => options.logical = false,
+            "--same-filesystem" | "-x" => options.same_filesystem = true,
+            "--relativize" => options.relativize = true,
+            "--absolutize" => options.absolutize = true,
+            "--shallow" => options.shallow = true,
+            "--dirs-only" => options.dirs_only = true,
+            "--exclude-hidden" => options.exclude_hidden = true,
+            "--mine-only" => options.mine_only = true,
+            "--require-concrete" => options.require_concrete = true,
+            "--no-recurse" => options.no_recurse = true,
+            "--bfs" | "--breadth-first" => options.breadth_first = true,
+            "--dfs" | "--depth-first" => options.breadth_first = false,
+            "--no-summary" => options.no_summary = true,
+            "--stats-json" => stats_json = true,
+            "--quiet" => options.quiet = true,
+            "--no-color" => no_color = true,
+            "--count-only" => options.count_only = true,
+            "--time" => options.time = true,
+            "--verbose" => options.verbose = true,
+            "--show-target" => options.show_target = true,
+            "--diff" => options.diff = true,
+            "--timing" => options.timing = true,
+            "--show-chain" => options.show_chain = true,
+            "--verify" => options.verify = true,
+            "--progress" => options.progress = true,
+            "--interactive" => options.interactive = true,
+            "--format=text" => options.format = Format::Text,
+            "--format=json" => options.format = Format::Json,
+            "--error-format=text" => options.error_format = Format::Text,
+            "--error-format=json" => options.error_format = Format::Json,
+            "--reflink=auto" => options.reflink = Reflink::Auto,
+            "--reflink=always" => options.reflink = Reflink::Always,
+            "--reflink=never" => options.reflink = Reflink::Never,
+            "--on-error=skip" => options.on_error = OnError::Skip,
+            "--on-error=abort" => options.on_error = OnError::Abort,
+            "--on-error=prompt" => options.on_error = OnError::Prompt,
+            "--backup" => options.backup = Some(".bak".into()),
+            flag if flag.starts_with("--backup=") => {
+                options.backup = Some(flag["--backup=".len()..].into())
+            }
+            "--log-template" => options.log_template = Some(flag_value(&mut args, &flag)?),
+            "--output" => output_path = Some(PathBuf::from(flag_value(&mut args, &flag)?)),
+            "--journal" => options.journal = Some(flag_value(&mut args, &flag)?.into()),
+            "--manifest" => options.manifest = Some(flag_value(&mut args, &flag)?.into()),
+            "--undo" => undo_journal = Some(PathBuf::from(flag_value(&mut args, &flag)?)),
+            "--glob" => options.glob = true,
+            "--include" => options.include.push(parse_flag_value(&mut args, &flag)?),
+            "--exclude" => options.exclude.push(parse_flag_value(&mut args, &flag)?),
+            "--exclude-from" => options.exclude_from = Some(flag_value(&mut args, &flag)?.into()),
+            "--jobs" => options.jobs = Some(parse_flag_value(&mut args, &flag)?),
+            "--tree-jobs" => options.tree_jobs = Some(parse_flag_value(&mut args, &flag)?),
+            "--skip-larger-than" => {
+                options.skip_larger_than = Some(parse_flag_value(&mut args, &flag)?)
+            }
+            "--ext" => options.extensions.extend(
+                flag_value(&mut args, &flag)?
+                    .split(',')
+                    .map(|ext| ext.trim_start_matches('.'))
+                    .filter(|ext| !ext.is_empty())
+                    .map(OsString::from),
+            ),
+            "--root" => options.root = Some(flag_value(&mut args, &flag)?.into()),
+            "--target-root" => options.target_root = Some(flag_value(&mut args, &flag)?.into()),
+            "--into" => options.into = Some(flag_value(&mut args, &flag)?.into()),
+            "--merge" => options.merge = true,
+            "--no-clobber" => options.no_clobber = true,
+            "--relative-output" => {
+                options.relative_to.get_or_insert_with(|| ".".into());
+            }
+            "--base" => options.relative_to = Some(flag_value(&mut args, &flag)?.into()),
+            "--max-total-bytes" => {
+                options.max_total_bytes = Some(parse_flag_value(&mut args, &flag)?)
+            }
+            "--limit" => options.limit = Some(parse_flag_value(&mut args, &flag)?),
+            "--newer-than" => {
+                let Timestamp(time) = parse_flag_value(&mut args, &flag)?;
+                options.newer_than = Some(time);
+            }
+            "--max-depth" => options.max_depth = Some(parse_flag_value(&mut args, &flag)?),
+            "--retry" => options.retry = parse_flag_value(&mut args, &flag)?,
+            _ => return Err(invalid_input(format!("Unknown option: {flag}"))),
+        }
+    }
+
+    if options.relativize && options.absolutize {
+        return Err(invalid_input(
+            "--relativize and --absolutize cannot be used together".to_string(),
+        ));
+    }
+    if options.merge && options.into.is_none() {
+        return Err(invalid_input(
+            "--merge can only be used with --into".to_string(),
+        ));
+    }
+    if options.no_clobber && !options.merge {
+        return Err(invalid_input(
+            "--no-clobber can only be used with --merge".to_string(),
+        ));
+    }
+    // The default permissions would clear the setuid and setgid bits kept
+    // along with the owner
+    if options.preserve_owner && options.preserve_exec {
+        return Err(invalid_input(
+            "--preserve-owner and --preserve-exec cannot be used together".to_string(),
+        ));
+    }
+
+    // Plain unless writing straight to a terminal
+    options.color = !no_color
+        && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        && output_path.is_none()
+        && io::stdout().is_terminal();
+
+    let mut output: Box<dyn Write> = match output_path {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+
+    if let Some(journal) = undo_journal {
+        undo(&mut output, &journal, &options)?;
+        return output.flush();
+    }
+
     // Peek for a help flag
     if let Some(first_entry) = args.by_ref().next()
         && !["-h", "--help"].contains(&first_entry.trim())
     {
-        // Take all input arguments
-        // Also handle the - case
-        let mut use_stdin = false;
-        let input = args.flat_map(|arg| {
-            if arg.trim() == "-" {
-                use_stdin = true;
-                None
-            } else {
-                Some(PathBuf::from(arg))
-            }
-        });
-
-        exec(
-            &mut io::stdout().lock(),
-            iter::once(PathBuf::from(first_entry)).chain(input),
-        )?;
+        // Stops at the first unreadable entry, keeping everything before
+        let mut read_error = None;
+        // Take all input arguments, with response files in place of @FILE,
+        // and stdin in place of -
+        let (mut summary, result) = match expand_response_files(iter::once(first_entry).chain(args))
+        {
+            Ok(input) => {
+                let input = input
+                    .into_iter()
+                    .map(path_from_url)
+                    .collect::<io::Result<Vec<_>>>()?;
+                let use_stdin = input.iter().any(|arg| arg.as_os_str() == "-");
+                if use_stdin && options.interactive {
+                    return Err(invalid_input(
+                        "--interactive answers are read from stdin, so - cannot be used"
+                            .to_string(),
+                    ));
+                }
+                if use_stdin && options.on_error == OnError::Prompt {
+                    return Err(invalid_input(
+                        "--on-error=prompt answers are read from stdin, so - cannot be used"
+                            .to_string(),
+                    ));
+                }
 
-        if use_stdin {
-            exec(
-                &mut io::stdout().lock(),
-                BufReader::new(io::stdin().lock())
-                    .lines()
-                    .map(|line| PathBuf::from(line.unwrap())),
-            )?;
+                resolve_all_with_summary(
+                    &mut output,
+                    // Not locked, since prompts also read from stdin without -
+                    input_paths(input, BufReader::new(io::stdin()), delimiter)
+                        .map_while(|entry| entry.map_err(|e| read_error = Some(e)).ok()),
+                    &options,
+                )
+            }
+            // Reported like a stopped run, so --stats-json is still printed
+            Err(e) => (Summary::default(), Err(e)),
+        };
+        let color = options.color && io::stderr().is_terminal();
+        if let Err(e) = result {
+            // Stopped by a failed input, with --on-error=abort or such
+            let Some(error) = DelinkError::from_io(&e) else {
+                return Err(e);
+            };
+            output.flush()?;
+            let path = Some(error.path());
+            write_error(&mut io::stderr(), options.error_format, path, &e, color)?;
+            summary.errors = summary.errors.max(1);
+        }
+        if let Some(e) = read_error {
+            write_error(&mut io::stderr(), options.error_format, None, &e, color)?;
+            summary.errors += 1;
         }
 
+        // After the log it summarizes, in the same output
+        if stats_json {
+            writeln!(output, "{}", summary.stats_json())?;
+        }
+        output.flush()?;
+        if summary.errors > 0 {
+            process::exit(1);
+        }
         Ok(())
     } else {
         eprintln!(
-            "Usage: {first_input} [PATH...]
-Usage: {first_input} [PATH...] - < [PATH...]
+            "Usage: {first_input} [OPTION...] [PATH...]
+Usage: {first_input} [OPTION...] [PATH...] - < [PATH...]
+Usage: {first_input} [OPTION...] --undo <JOURNAL>
 
     {first_input} recursively resolves all given soft links.
     Paths can be given as arguments.
-    THe special argument - adds newline delimited stdin to the input.
+    The special argument - adds newline delimited stdin to the input, in its
+    place among the other paths.
+    With --null or --delimiter, stdin is split on another byte instead.
+    An argument @FILE adds the newline delimited paths in FILE in its place.
+    Arguments can also be file:// URLs, which are percent-decoded.
     All directories and links to directories will be unwrapped.
-    If this exits with an error, links may be deleted but not fully replaced.
+    A trailing / on a link to a directory still replaces the link itself.
+    Inputs that fail are reported as ERROR <PATH>: <MESSAGE> on stderr,
+    the remaining inputs are still processed, and the exit code is 1.
+    Entries that cannot be accessed are reported as ERROR PERMISSION <PATH>
+    and skipped without stopping the rest of their input.
+    With --on-error=abort, the first failure stops the run instead.
+    If this exits with an error, file links may be deleted but not replaced.
+    Directory links are only replaced once fully populated.
+
+OPTIONS
+    --null, -0: Split stdin paths on NUL bytes instead of newlines.
+    --delimiter <CHAR>: Split stdin paths on CHAR, a single byte such as ',',
+        instead of newlines.
+    --dry-run: Log all actions without modifying the filesystem.
+    --hardlink: Hard link files instead of copying, when on one filesystem.
+    --dedup: Hard link repeated files to their first copy instead of copying.
+        Ignores --jobs and --tree-jobs.
+    --move: Move files onto links to them, when on one filesystem. Files with
+        other links to them among the inputs are copied instead.
+    --reflink=<auto|always|never>: Clone copied files to keep them sparse and
+        copy-on-write when supported (default), always, or never (Linux only).
+    --preserve-timestamps: Keep access and modification times of copied files.
+    --preserve-link-times: Keep access and modification times of recreated links.
+    --preserve-dir-perms: Keep the permissions of populated directories.
+    --flatten[=<rename|skip>]: Populate the files of nested directories straight
+        into each populated directory. Later files with a name already taken
+        get a counter added, like name-1.ext (default), or are skipped.
+    --preserve-xattr: Keep extended attributes of copied files (Linux only).
+    --preserve-owner: Keep the owner and group of copies, usually as root (Unix only).
+    --preserve-exec: Only keep the execute bits of copies, with the permissions
+        of a new file otherwise (Unix only). Cannot be used with
+        --preserve-owner.
+    --recreate-special: Recreate FIFOs, sockets, and devices with mknod instead
+        of skipping them (Unix only).
+    --prune-broken: Remove links that point to nothing.
+    --only-broken: Leave links that point to something in place.
+    --no-canonicalize: Resolve links one step, even if that is another link.
+    --logical: Resolve links as written, applying .. before following links.
+    --physical: Resolve links to their physical canonical path (default).
+    --same-filesystem, -x: Do not populate directories from other filesystems.
+    --relativize: Rewrite absolute links as relative links, instead of resolving.
+    --absolutize: Rewrite relative links as absolute links, instead of resolving.
+        Broken links are rewritten from what is stored in them, for both.
+    --shallow: Keep links inside of populated directories as links.
+    --dirs-only: Only resolve links to directories, leaving links to files.
+    --no-recurse: Only resolve links directly inside of input directories.
+    --bfs, --breadth-first: Walk directories one level at a time.
+    --dfs, --depth-first: Finish each subdirectory before the next entry (default).
+    --no-summary: Do not print a SUMMARY line after each run.
+    --stats-json: Print a JSON object of all totals and elapsed time at the end,
+        after the log in the same output, even if the run stops early.
+    --quiet: Do not print any OUTPUT lines. Errors are still printed.
+    --no-color: Print OUTPUT and ERROR lines without color. Colors are only
+        used on terminals, and never when NO_COLOR is set.
+    --count-only: Only print the SUMMARY line, even with --quiet or --no-summary.
+    --time: Print an ELAPSED line once done, even with --quiet.
+    --verbose: Also print VISIT, RESOLVED, and SKIP FILE lines.
+    --show-target: Also print what replaced links stored, as
+        COPY <DEST> (was -> <TARGET>) => <LINK>. Not used with --log-template.
+    --diff: Print a unified diff under each COPY line that replaces an existing
+        file, such as with --merge.
+    --timing: Add the size, time taken, and speed of each copy to its COPY line,
+        printed once the copy finishes.
+    --show-chain: Print every link passed through on the way to each DEST.
+    --verify: Check for links left in place without a reason once done.
+    --interactive: Ask before replacing each link. Cannot be used with -.
+    --progress: Show a status line on stderr instead of OUTPUT lines.
+    --on-error=<skip|abort|prompt>: Skip what failed and continue (default),
+        stop the run, or ask which to do. Prompts cannot be used with -.
+    --format=<text|json>: Print OUTPUT lines as text (default) or JSON objects.
+    --error-format=<text|json>: Print ERROR and WARNING lines as text (default)
+        or JSON objects with path and message fields, where a null path is
+        stdin. WARNING objects also have a level field of warning.
+    --retry <N>: Try failed copies, renames, and removals up to N more times,
+        waiting longer each time, when the error is likely to pass.
+    --jobs <N>: Process up to N input paths at once.
+    --tree-jobs <N>: Populate up to N entries of each directory link at once.
+    --log-template <TEMPLATE>: Print OUTPUT lines with {{action}}, {{dest}}, and
+        {{link}} in TEMPLATE filled in. Paths are quoted.
+    --max-depth <N>: Do not descend more than N directories below each input.
+    --max-total-bytes <N>: Stop the run before copying more than N bytes.
+        Ignores --jobs and --tree-jobs.
+    --limit <N>: Stop the run once N links are replaced. Entries of populated
+        directories are not counted. Ignores --jobs and --tree-jobs.
+    --ext <EXT,...>: Only replace links to files with one of these extensions.
+    --skip-larger-than <N>: Leave links to files larger than N bytes in place.
+    --root <DIR>: Do not resolve links to anything outside of DIR.
+    --target-root <DIR>: Follow absolute link targets inside of DIR, as if it
+        were /, such as for a mounted image of another system.
+    --into <DIR>: Copy each PATH into DIR with every link resolved, leaving PATH
+        itself untouched. DIR is created if missing.
+    --merge: Merge copies made with --into into existing copies. Anything else
+        in the way is replaced, or renamed first with --backup.
+    --no-clobber: Keep files already in the way with --merge, instead of
+        replacing them.
+    --glob: Expand each PATH as a glob pattern, such as 'links/*'. Patterns that
+        match nothing are used as is.
+    --include <GLOB>: Only resolve links with absolute paths matching a GLOB.
+    --exclude <GLOB>: Do not resolve links with absolute paths matching GLOB.
+    --exclude-hidden: Leave dotfiles out of populated directories.
+    --exclude-from <FILE>: Do not resolve the links listed, one per line, in FILE.
+    --newer-than <TIME>: Only resolve links last modified at or after TIME, as
+        RFC 3339 (2024-01-02T03:04:05Z) or @ and seconds since the Unix epoch.
+    --mine-only: Only resolve links owned by the current user.
+    --require-concrete: Only resolve links that point straight to a file or
+        directory, not to another link.
+    --relative-output: Print paths relative to the current directory.
+    --base <DIR>: Print paths relative to DIR.
+    --backup[=SUFFIX]: Rename replaced links with SUFFIX (default .bak).
+    --output <FILE>: Write OUTPUT lines to FILE instead of stdout.
+    --journal <FILE>: Append a record of every replaced link to FILE.
+    --manifest <FILE>: Write every replaced link and the canonical path it was
+        resolved from to FILE, as sorted tab separated lines.
+    --undo <FILE>: Recreate the links recorded in a journal FILE.
 
 OUTPUT
     COPY <DEST> => <LINK>: Fill LINK with the contents it pointed to.
+    HARDLINK <DEST> => <LINK>: Replace LINK with a hard link to DEST.
+    MOVE <DEST> => <LINK>: DEST was moved to replace LINK.
     POPULATE <DEST> => <LINK>: Fill LINK with the directory it pointed to.
+    SYMLINK <TARGET> => <LINK>: LINK was recreated as a link to TARGET.
+    MKNOD <DEST> => <LINK>: LINK was recreated as the same kind of node as DEST.
+    RELATIVIZE <LINK> => <TARGET>: LINK was rewritten to the relative TARGET.
+    ABSOLUTIZE <LINK> => <TARGET>: LINK was rewritten to the absolute TARGET.
+    BACKUP <LINK> => <BACKUP>: Original LINK was renamed to BACKUP.
+    RESTORE <TARGET> => <LINK>: LINK was recreated from a journal.
+    VISIT <PATH>: PATH is being checked (--verbose only).
+    RESOLVED <LINK> -> <DEST>: LINK points to DEST (--verbose only).
+    CHAIN <LINK> -> ... -> <DEST>: LINK leads to DEST through each link in
+        between (--show-chain only).
+    SKIP FILE <PATH>: PATH is not a link or directory (--verbose only).
+    SKIP BROKEN <LINK>: Links that point to nothing are left as is.
+    PRUNE BROKEN <LINK>: LINK pointed to nothing and was removed.
+    SKIP VALID <LINK>: LINK points to something, with --only-broken.
+    SKIP UNCHANGED <PATH>: PATH already has the contents of its destination.
+    SKIP OUTSIDE ROOT <DEST>: DEST is outside of the --root directory.
+    SKIP DECLINED <LINK>: Replacing LINK was declined with --interactive.
+    SKIP XDEV <DEST>: DEST is on another filesystem, with --same-filesystem.
     SKIP SELF REFERENCE <FILE>: Invalid soft links are ignored.
+    SKIP CYCLE <LINK>: Links that loop through other links are ignored.
     SKIP RECURSIVE <LINK>: Link to a parent directory are ignored.
+    SKIP FILTER <LINK>: Link was filtered out by --include or --exclude(-from).
+    SKIP DEPTH <PATH>: Directories past the maximum depth, or below inputs with
+        --no-recurse (--verbose only), are not descended.
+    SKIP OLD <LINK>: LINK was last modified before --newer-than.
+    SKIP VANISHED <DEST>: DEST was removed after a link was resolved to it.
+    SKIP NOT OWNER <LINK>: LINK is owned by another user, with --mine-only.
+    SKIP INDIRECT <LINK>: LINK points to another link, with --require-concrete.
+    SKIP HIDDEN <PATH>: PATH was left out of a populated directory, with
+        --exclude-hidden.
+    SKIP CASE COLLISION <PATH>: PATH would replace an entry differing only in
+        case, when populating on a case-insensitive filesystem.
+    SKIP COLLISION <PATH>: PATH has the name of an earlier file, with
+        --flatten=skip.
+    SKIP SPECIAL <LINK>: FIFOs, sockets, and devices are not copied.
+    SKIP FILE LINK <LINK>: LINK points to a file, with --dirs-only.
+    SKIP EXT <LINK>: LINK points to a file without an --ext extension.
+    SKIP LARGE <LINK>: LINK points to a file larger than --skip-larger-than.
+    SKIP EXISTS <PATH>: PATH already exists in a copy merged with --no-clobber.
+    SKIP PATH TOO LONG <PATH>: PATH is longer than the platform allows, such as
+        deep inside of nested populated directories.
+    SKIP CONTAINED <PATH>: PATH is inside of, or repeats, another input.
     SKIP INPUT <LINK>: Input link does not exist or is invalid.
+    SKIP NOT A LINK <PATH>: Input PATH is neither a link nor a directory.
+    SKIP DANGLING <LINK>: Input link leads to something that does not exist.
+    ABORT BYTE LIMIT <LINK>: Copying to LINK would pass --max-total-bytes.
+    STOP LIMIT REACHED <LINK>: LINK and everything after it is left in place,
+        with --limit.
+    VERIFY LEFTOVER <LINK>: LINK is still a link after the run, with --verify.
+    VERIFY OK: No links were left in place without a reason, with --verify.
+    SUMMARY copies=<N> hardlinks=<N> moves=<N> populated=<N> skipped=<N> errors=<N> bytes=<N> distinct_targets=<N>:
+        Action totals, the total size of copied files, and how many different
+        files and directories replaced links. Fewer targets than files
+        replaced means --dedup can save space.
+    ELAPSED <DURATION>: Wall-clock time the run took, with --time.
 "
         );
         Ok(())
     }
 }
-
-#[cfg(test)]
-mod tests {
-    use std::{
-        fs::{File, create_dir},
-        os::unix,
-    };
-
-    use mktemp::Temp;
-
-    use super::*;
-
-    #[test]
-    fn basic_resolve() {
-        let dir = Temp::new_dir().unwrap();
-
-        let linked_path = dir.join("linked_file");
-        let symlink_path = dir.join("symlink");
-        let _linked_file = File::create(&linked_path).unwrap();
-        let _symlink = unix::fs::symlink(&linked_path, &symlink_path);
-
-        let expected = format!("COPY {linked_path:?} => {symlink_path:?}\n");
-        let mut buffer = Vec::new();
-        exec(&mut buffer, [linked_path, symlink_path.clone()]).unwrap();
-        assert_eq!(str::from_utf8(&buffer).unwrap(), expected);
-        assert!(symlink_path.is_file());
-    }
-
-    #[test]
-    fn non_existent() {
-        let dir = Temp::new_dir().unwrap();
-
-        let symlink_path = dir.join("symlink");
-
-        let expected = format!("SKIP INPUT {symlink_path:?}\n");
-        let mut buffer = Vec::new();
-        exec(&mut buffer, [symlink_path.clone()]).unwrap();
-        assert_eq!(str::from_utf8(&buffer).unwrap(), expected);
-        assert!(!symlink_path.exists());
-    }
-
-    #[test]
-    fn link_to_self() {
-        let dir = Temp::new_dir().unwrap();
-
-        let symlink_path = dir.join("symlink");
-        let _symlink = unix::fs::symlink(&symlink_path, &symlink_path);
-
-        let expected = format!("SKIP SELF REFERENCE {symlink_path:?}\n");
-        let mut buffer = Vec::new();
-        exec(&mut buffer, [dir.to_path_buf()]).unwrap();
-        assert_eq!(str::from_utf8(&buffer).unwrap(), expected);
-        assert!(symlink_path.is_symlink());
-    }
-
-    #[test]
-    fn dir_link() {
-        let dir = Temp::new_dir().unwrap();
-
-        let subdir = dir.join("real_dir");
-        let symlink_path = dir.join("symlink");
-        create_dir(&subdir).unwrap();
-
-        let linked_path = subdir.join("linked_file");
-        let _linked_file = File::create(&linked_path).unwrap();
-        let _symlink = unix::fs::symlink(&subdir, &symlink_path);
-
-        let expected = format!(
-            "POPULATE {subdir:?} => {symlink_path:?}\nCOPY {linked_path:?} => {:?}\n",
-            symlink_path.join(linked_path.file_name().unwrap())
-        );
-        let mut buffer = Vec::new();
-        exec(&mut buffer, [symlink_path.clone()]).unwrap();
-        assert_eq!(str::from_utf8(&buffer).unwrap(), expected);
-        assert!(symlink_path.is_dir())
-    }
-
-    #[test]
-    fn nested_dir_link() {
-        let dir = Temp::new_dir().unwrap();
-
-        let subdir = dir.join("real_dir");
-        let sub_subdir = subdir.join("inner");
-        let symlink_path = dir.join("symlink");
-        create_dir(&subdir).unwrap();
-        create_dir(&sub_subdir).unwrap();
-
-        let linked_path = sub_subdir.join("linked_file");
-        let _linked_file = File::create(&linked_path).unwrap();
-        let _symlink = unix::fs::symlink(&subdir, &symlink_path);
-
-        let expected = format!(
-            "POPULATE {subdir:?} => {symlink_path:?}\nPOPULATE {sub_subdir:?} => {:?}\nCOPY {linked_path:?} => {:?}\n",
-            symlink_path.join(sub_subdir.file_name().unwrap()),
-            symlink_path
-                .join(sub_subdir.file_name().unwrap())
-                .join(linked_path.file_name().unwrap())
-        );
-        let mut buffer = Vec::new();
-        exec(&mut buffer, [symlink_path.clone()]).unwrap();
-        assert_eq!(str::from_utf8(&buffer).unwrap(), expected);
-        assert!(symlink_path.is_dir());
-        assert!(symlink_path.join("inner").is_dir())
-    }
-
-    #[test]
-    fn recursive_dir_link() {
-        let dir = Temp::new_dir().unwrap();
-
-        let subdir = dir.join("real_dir");
-        let symlink_path = subdir.join("symlink");
-        create_dir(&subdir).unwrap();
-
-        let linked_path = subdir.join("linked_file");
-        let _linked_file = File::create(&linked_path).unwrap();
-        let _symlink = unix::fs::symlink(&subdir, &symlink_path);
-
-        let expected = format!("SKIP RECURSIVE {symlink_path:?}\n",);
-        let mut buffer = Vec::new();
-        exec(&mut buffer, [symlink_path.clone()]).unwrap();
-        assert_eq!(str::from_utf8(&buffer).unwrap(), expected);
-        assert!(symlink_path.is_symlink())
-    }
-}
@@ -0,0 +1,39 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Sorted record of where each replaced link came from, for provenance.
+//!
+//! Each record is `<LINK>\t<SOURCE>`, where `SOURCE` is the canonical path
+//! the link was resolved from. Paths are escaped like in the journal.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::journal::escape;
+
+/// Writes `entries` of links and their sources to `path`, sorted by link.
+///
+/// Replaces anything already at `path`.
+pub(crate) fn write(path: &Path, entries: HashMap<PathBuf, PathBuf>) -> io::Result<()> {
+    let mut entries: Vec<_> = entries.into_iter().collect();
+    entries.sort();
+
+    let mut file = BufWriter::new(File::create(path)?);
+    let mut line = Vec::new();
+    for (link, source) in entries {
+        line.clear();
+        escape(link.as_os_str(), &mut line);
+        line.push(b'\t');
+        escape(source.as_os_str(), &mut line);
+        line.push(b'\n');
+        file.write_all(&line)?;
+    }
+    file.flush()
+}
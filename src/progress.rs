@@ -0,0 +1,75 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Status reporting used in place of per-action logging.
+
+use std::{
+    io::{self, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::Summary;
+
+/// Receives every action taken during a run.
+pub(crate) trait Progress {
+    /// Called once before the first update, with an estimate of how many
+    /// actions the run takes.
+    fn start(&mut self, total: usize);
+    /// Called after each action with the running totals, the number of
+    /// actions taken so far, and the path acted on.
+    fn update(&mut self, summary: &Summary, done: usize, path: &Path);
+    /// Called once after the last update.
+    fn finish(&mut self);
+}
+
+/// Single carriage return updated line on stderr.
+#[derive(Debug, Default)]
+pub(crate) struct StatusLine {
+    last_render: Option<Instant>,
+    total: Option<usize>,
+}
+
+impl StatusLine {
+    /// Minimum time between renders, so tiny files are not bottlenecked on
+    /// the terminal.
+    const INTERVAL: Duration = Duration::from_millis(100);
+}
+
+impl Progress for StatusLine {
+    fn start(&mut self, total: usize) {
+        self.total = Some(total);
+    }
+
+    fn update(&mut self, summary: &Summary, done: usize, path: &Path) {
+        if self
+            .last_render
+            .is_some_and(|last| last.elapsed() < Self::INTERVAL)
+        {
+            return;
+        }
+        self.last_render = Some(Instant::now());
+
+        // Clears the rest of the previous line
+        let mut stderr = io::stderr().lock();
+        let _ = match self.total {
+            // The total is only an estimate, so it may be passed
+            Some(total) => write!(
+                stderr,
+                "\r{done}/{} {summary} {path:?}\x1b[K",
+                total.max(done)
+            ),
+            None => write!(stderr, "\r{summary} {path:?}\x1b[K"),
+        };
+        let _ = stderr.flush();
+    }
+
+    fn finish(&mut self) {
+        if self.last_render.take().is_some() {
+            eprintln!();
+        }
+    }
+}
@@ -0,0 +1,181 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Temporary files and directories for tests, removed once dropped.
+
+use std::{
+    env,
+    fs::{self, DirBuilder, OpenOptions, Permissions},
+    io::{self, ErrorKind},
+    ops::Deref,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Names tried before giving up, when others are already taken.
+const ATTEMPTS: usize = 16;
+
+/// Temporary file or directory, removed along with everything inside of it
+/// once dropped.
+///
+/// Tests that panic still drop it while unwinding, so removal never panics
+/// itself, which would abort the whole test binary.
+#[derive(Debug)]
+pub(crate) struct Temp {
+    path: PathBuf,
+}
+
+impl Temp {
+    /// Creates an empty directory in [`env::temp_dir`].
+    pub(crate) fn new_dir() -> io::Result<Self> {
+        Self::new_dir_in(env::temp_dir())
+    }
+
+    /// Creates an empty directory in `dir`.
+    pub(crate) fn new_dir_in<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        Self::create_in(dir.as_ref(), |path| DirBuilder::new().create(path))
+    }
+
+    /// Creates an empty file in [`env::temp_dir`].
+    pub(crate) fn new_file() -> io::Result<Self> {
+        Self::create_in(&env::temp_dir(), |path| {
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)
+                .map(drop)
+        })
+    }
+
+    /// Creates something at an unused path in `dir` with `create`, which
+    /// fails if anything is already there.
+    fn create_in(dir: &Path, create: impl Fn(&Path) -> io::Result<()>) -> io::Result<Self> {
+        static CREATED: AtomicUsize = AtomicUsize::new(0);
+
+        let mut taken = None;
+        for _ in 0..ATTEMPTS {
+            let path = dir.join(unique_name(CREATED.fetch_add(1, Ordering::Relaxed)));
+            match create(&path) {
+                Ok(()) => return Ok(Self { path }),
+                // Left behind by an earlier process with the same ID
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => taken = Some(e),
+                Err(e) => return Err(e),
+            }
+        }
+        Err(taken.unwrap_or_else(|| ErrorKind::AlreadyExists.into()))
+    }
+}
+
+/// Name that no other test in any running process can pick.
+fn unique_name(count: usize) -> String {
+    // Tells apart processes that reuse the ID of one that left files behind
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.subsec_nanos());
+    format!("delink-test-{}-{count}-{nanos}", process::id())
+}
+
+impl Deref for Temp {
+    type Target = PathBuf;
+
+    fn deref(&self) -> &Self::Target {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for Temp {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Temp {
+    fn drop(&mut self) {
+        let Ok(metadata) = self.path.symlink_metadata() else {
+            return;
+        };
+        if !metadata.is_dir() {
+            let _ = fs::remove_file(&self.path);
+        } else if fs::remove_dir_all(&self.path).is_err() {
+            // Tests can leave read-only directories, which keep their entries
+            make_writable(&self.path);
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+/// Allows removing everything inside of `path`, without following links.
+fn make_writable(path: &Path) {
+    let Ok(metadata) = path.symlink_metadata() else {
+        return;
+    };
+    if metadata.is_symlink() {
+        return;
+    }
+
+    let _ = fs::set_permissions(path, writable(metadata.permissions()));
+    if metadata.is_dir()
+        && let Ok(entries) = fs::read_dir(path)
+    {
+        for entry in entries.flatten() {
+            make_writable(&entry.path());
+        }
+    }
+}
+
+#[cfg(unix)]
+fn writable(permissions: Permissions) -> Permissions {
+    use std::os::unix::fs::PermissionsExt;
+    Permissions::from_mode(permissions.mode() | 0o700)
+}
+
+#[cfg(windows)]
+fn writable(mut permissions: Permissions) -> Permissions {
+    // Read-only files cannot be removed on Windows
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+    permissions
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, panic};
+
+    use super::*;
+
+    #[test]
+    fn removes_read_only_tree() {
+        let dir = Temp::new_dir().unwrap();
+        let path = dir.to_path_buf();
+
+        let inner = path.join("inner");
+        fs::create_dir(&inner).unwrap();
+        File::create(inner.join("file")).unwrap();
+        let mut permissions = inner.metadata().unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(inner.join("file"), permissions.clone()).unwrap();
+        fs::set_permissions(&inner, permissions).unwrap();
+
+        drop(dir);
+        assert!(path.symlink_metadata().is_err(), "{path:?} was left behind");
+    }
+
+    #[test]
+    fn removes_on_panic() {
+        let mut path = None;
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let file = Temp::new_file().unwrap();
+            path = Some(file.to_path_buf());
+            panic!("injected failure");
+        }));
+
+        assert!(result.is_err());
+        let path = path.unwrap();
+        assert!(path.symlink_metadata().is_err(), "{path:?} was left behind");
+    }
+}